@@ -27,13 +27,9 @@ use timely::dataflow::{InputHandle, ProbeHandle};
 use timely::dataflow::operators::{Probe, Capture, capture::Replay};
 
 use timely::dataflow::channels::pact::Pipeline;
-use timely::dataflow::operators::Operator;
-use timely::dataflow::operators::Broadcast;
-use timely::dataflow::Stream;
-use timely::dataflow::Scope;
-use timely::ExchangeData;
 
-use dynamic_scaling_mechanism::{ControlInst, Control};
+use dynamic_scaling_mechanism::ControlInst;
+use dynamic_scaling_mechanism::control::ControlHandle;
 use dynamic_scaling_mechanism::operator::StatefulOperator;
 
 use nexmark::event::Event;
@@ -46,42 +42,6 @@ fn calculate_hash<T: Hash>(t: &T) -> u64 {
     h.finish()
 }
 
-#[allow(dead_code)]
-fn verify<S: Scope, T: ExchangeData+Ord+::std::fmt::Debug>(correct: &Stream<S, T>, output: &Stream<S, T>) -> Stream<S, ()> {
-    use timely::dataflow::channels::pact::Exchange;
-    use std::collections::HashMap;
-    let mut in1_pending: HashMap<_, Vec<_>> = Default::default();
-    let mut in2_pending: HashMap<_, Vec<_>> = Default::default();
-    let mut data_buffer: Vec<T> = Vec::new();
-    correct.binary_notify(&output, Exchange::new(|_| 0), Exchange::new(|_| 0), "Verify", vec![],
-        move |in1, in2, _out, not| {
-            in1.for_each(|time, data| {
-                data.swap(&mut data_buffer);
-                in1_pending.entry(time.time().clone()).or_insert_with(Default::default).extend(data_buffer.drain(..));
-                not.notify_at(time.retain());
-            });
-            in2.for_each(|time, data| {
-                data.swap(&mut data_buffer);
-                in2_pending.entry(time.time().clone()).or_insert_with(Default::default).extend(data_buffer.drain(..));
-                not.notify_at(time.retain());
-            });
-            not.for_each(|time, _, _| {
-                let mut v1 = in1_pending.remove(time.time()).unwrap_or_default();
-                let mut v2 = in2_pending.remove(time.time()).unwrap_or_default();
-                v1.sort();
-                v2.sort();
-                assert_eq!(v1.len(), v2.len());
-                let i1 = v1.iter();
-                let i2 = v2.iter();
-                for (a, b) in i1.zip(i2) {
-//                    println!("a: {:?}, b: {:?}", a, b);
-                    assert_eq!(a, b, " at {:?}", time.time());
-                }
-            })
-        }
-    )
-}
-
 fn main() {
 
     let matches = App::new("word_count")
@@ -114,9 +74,13 @@ fn main() {
         let peers = worker.peers();
         let index = worker.index();
 
+        // Validate the migration plan before constructing any dataflow
+        let mut instructions: Vec<(u64, Vec<ControlInst>)> = map_mode.instructions(peers, duration_ns)
+            .unwrap_or_else(|e| panic!("Invalid migration plan: {}", e));
+
         // Declare re-used input, control and probe handles.
         let mut input = InputHandle::new();
-        let mut control_input = InputHandle::new();
+        let mut control_input = ControlHandle::new();
         let mut probe = ProbeHandle::new();
 
         {
@@ -181,7 +145,7 @@ fn main() {
                 bids_stream.capture_into(bids.clone());
                 auctions_stream.capture_into(auctions.clone());
                 people_stream.capture_into(people.clone());
-                control_input.to_stream(scope).broadcast().capture_into(control.clone());
+                control_input.to_stream(scope).capture_into(control.clone());
             });
 
 
@@ -345,8 +309,6 @@ fn main() {
         config1.insert("first-event-number", format!("{}", index));
         let mut config = nexmark::config::NEXMarkConfig::new(&config1);
 
-        let mut instructions: Vec<(u64, Vec<ControlInst>)> = map_mode.instructions(peers, duration_ns).unwrap();
-
         if index == 0 {
             println!("time_dilation\t{}", time_dilation);
             println!("bin_shift\t{}", ::dynamic_scaling_mechanism::BIN_SHIFT);
@@ -357,7 +319,6 @@ fn main() {
             }
         }
 
-        let mut control_sequence = 0;
         let mut control_input = Some(control_input);
         if index != 0 {
             control_input.take().unwrap().close();
@@ -365,12 +326,7 @@ fn main() {
             let control_input = control_input.as_mut().unwrap();
             if instructions.get(0).map_or(false, |(ts, _)| *ts == 0) {
                 let (_ts, ctrl_instructions) = instructions.remove(0);
-                let count = ctrl_instructions.len();
-
-                for instruction in ctrl_instructions {
-                    control_input.send(Control::new(control_sequence, count, instruction));
-                }
-                control_sequence += 1;
+                control_input.send(ctrl_instructions).expect("plans are validated");
             }
             control_input.advance_to(1);
         }
@@ -425,19 +381,10 @@ fn main() {
                         && instructions.get(0).map(|&(ts, _)| ts as usize + count <= *control_input.time()).unwrap_or(false)
                         {
                             let (ts, ctrl_instructions) = instructions.remove(0);
-                            let count = ctrl_instructions.len();
-
-                            if *control_input.time() < ts as usize + count {
-                                control_input.advance_to(ts as usize + count);
-                            }
+                            control_input.send_at(ts as usize + count, ctrl_instructions).expect("plans are validated");
 
                             println!("control_time\t{}", control_input.time());
 
-                            for instruction in ctrl_instructions {
-                                control_input.send(Control::new(control_sequence, count, instruction));
-                            }
-
-                            control_sequence += 1;
                             last_migrated = Some(*control_input.time());
                         }
                 }
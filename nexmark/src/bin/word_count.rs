@@ -25,16 +25,15 @@ use rand::rngs::SmallRng;
 use streaming_harness::util::ToNanos;
 
 use timely::dataflow::{InputHandle, ProbeHandle};
-use timely::dataflow::operators::{Broadcast, Operator, Probe};
+use timely::dataflow::operators::{Operator, Probe};
 
 use timely::dataflow::channels::pact::{Exchange, Pipeline};
-use timely::dataflow::Stream;
-use timely::dataflow::Scope;
-use timely::ExchangeData;
 
-use dynamic_scaling_mechanism::Control;
+use dynamic_scaling_mechanism::control::ControlHandle;
 use dynamic_scaling_mechanism::notificator::{Notify, TotalOrderFrontierNotificator};
 use dynamic_scaling_mechanism::state_machine::BinnedStateMachine;
+use dynamic_scaling_mechanism::probe::MigrationProbe;
+use dynamic_scaling_mechanism::testing::verify;
 
 use nexmark::tools::ExperimentMapMode;
 use timely::dataflow::operators::input::Handle;
@@ -70,45 +69,10 @@ impl WordGenerator {
     }
 }
 
-#[allow(dead_code)]
-fn verify<S: Scope, T: ExchangeData+Ord+::std::fmt::Debug>(correct: &Stream<S, T>, output: &Stream<S, T>) -> Stream<S, ()> {
-    use timely::dataflow::channels::pact::Exchange;
-    use std::collections::HashMap;
-    let mut in1_pending: HashMap<_, Vec<_>> = Default::default();
-    let mut in2_pending: HashMap<_, Vec<_>> = Default::default();
-    let mut data_buffer: Vec<T> = Vec::new();
-    correct.binary_notify(&output, Exchange::new(|_| 0), Exchange::new(|_| 0), "Verify", vec![],
-        move |in1, in2, _out, not| {
-            in1.for_each(|time, data| {
-                data.swap(&mut data_buffer);
-                in1_pending.entry(time.time().clone()).or_insert_with(Default::default).extend(data_buffer.drain(..));
-                not.notify_at(time.retain());
-            });
-            in2.for_each(|time, data| {
-                data.swap(&mut data_buffer);
-                in2_pending.entry(time.time().clone()).or_insert_with(Default::default).extend(data_buffer.drain(..));
-                not.notify_at(time.retain());
-            });
-            not.for_each(|time, _, _| {
-                let mut v1 = in1_pending.remove(time.time()).unwrap_or_default();
-                let mut v2 = in2_pending.remove(time.time()).unwrap_or_default();
-                v1.sort();
-                v2.sort();
-                assert_eq!(v1.len(), v2.len());
-                let i1 = v1.iter();
-                let i2 = v2.iter();
-                for (a, b) in i1.zip(i2) {
-//                    println!("a: {:?}, b: {:?}", a, b);
-                    assert_eq!(a, b, " at {:?}", time.time());
-                }
-            })
-        }
-    )
-}
-
 #[derive(Ord, PartialOrd, Eq, PartialEq, Copy, Clone, Debug, Hash)]
 enum Backend {
     HashMap,
+    HashMapCombine,
     HashMapNative,
     Vector,
     VectorNative,
@@ -123,7 +87,7 @@ fn main() {
         .arg(Arg::with_name("domain").long("domain").takes_value(true).required(true))
         .arg(Arg::with_name("validate").long("validate"))
         .arg(Arg::with_name("timely").multiple(true))
-        .arg(Arg::with_name("backend").long("backend").takes_value(true).possible_values(&["hashmap", "hashmapnative", "vec", "vecnative"]).default_value("hashmap"))
+        .arg(Arg::with_name("backend").long("backend").takes_value(true).possible_values(&["hashmap", "hashmapcombine", "hashmapnative", "vec", "vecnative"]).default_value("hashmap"))
         .get_matches();
 
     let rate: u64 = matches.value_of("rate").expect("rate absent").parse::<u64>().expect("couldn't parse rate");
@@ -138,6 +102,7 @@ fn main() {
 
     let backend: Backend = match matches.value_of("backend").expect("backend missing") {
         "hashmap" => Backend::HashMap,
+        "hashmapcombine" => Backend::HashMapCombine,
         "hashmapnative" => Backend::HashMapNative,
         "vec" => Backend::Vector,
         "vecnative" => Backend::VectorNative,
@@ -155,9 +120,13 @@ fn main() {
         let peers = worker.peers();
         let index = worker.index();
 
+        // Validate the migration plan before constructing any dataflow
+        let mut instructions = map_mode.instructions(peers, duration_ns)
+            .unwrap_or_else(|e| panic!("Invalid migration plan: {}", e));
+
         // Declare re-used input, control and probe handles.
         let mut input: Handle<_, ()> = InputHandle::new();
-        let mut control_input = InputHandle::new();
+        let mut control_input = ControlHandle::new();
         // let mut control_input_2 = InputHandle::new();
         let mut probe = ProbeHandle::new();
         let probe2 = ProbeHandle::clone(&mut probe);
@@ -182,8 +151,9 @@ fn main() {
         };
 
         // Construct the dataflow
-        worker.dataflow(|scope: &mut ::timely::dataflow::scopes::Child<_, usize>| {
-            let control = control_input.to_stream(scope).broadcast();
+        let migration_probe = worker.dataflow(|scope: &mut ::timely::dataflow::scopes::Child<_, usize>| {
+            let control = control_input.to_stream(scope);
+            let mut migration_probe = MigrationProbe::new(&control);
 
             // Construct the data generator
             let input = input
@@ -264,7 +234,15 @@ fn main() {
                             *agg += val;
                             (false, Some((*key, *agg)))
                         }, |key| calculate_hash(key), &control)
-                        .probe_with(&mut probe))
+                        .probe_with(&mut probe)
+                        .probe_with(migration_probe.handle()))
+                },
+                Backend::HashMapCombine => {
+                    use dynamic_scaling_mechanism::aggregate::StatefulAggregate;
+                    Some(input
+                        .stateful_aggregate(|agg: &mut u64, val| *agg += val, &control)
+                        .probe_with(&mut probe)
+                        .probe_with(migration_probe.handle()))
                 },
                 Backend::HashMapNative => {
                     Some(input
@@ -294,7 +272,9 @@ fn main() {
                                  }
                              }
                          })
-                         .probe_with(&mut probe))
+                         .probe_with(&mut probe)
+                         // Does not migrate, configurations complete with the data up to their time
+                         .probe_with(migration_probe.handle()))
                 }
                 _ => None,
             };
@@ -319,7 +299,8 @@ fn main() {
                                 session.give((key, states[position]));
                             }
                         })
-                        .probe_with(&mut probe))
+                        .probe_with(&mut probe)
+                        .probe_with(migration_probe.handle()))
                 },
                 Backend::VectorNative => {
                     Some(input
@@ -353,7 +334,9 @@ fn main() {
                                  }
                              }
                          })
-                         .probe_with(&mut probe))
+                         .probe_with(&mut probe)
+                         // Does not migrate, configurations complete with the data up to their time
+                         .probe_with(migration_probe.handle()))
                 },
                 _ => None,
             };
@@ -372,10 +355,9 @@ fn main() {
                     verify(&vec_output, &correct).probe_with(&mut probe);
                 }
             }
+            migration_probe
         });
 
-        let mut instructions = map_mode.instructions(peers, duration_ns).unwrap();
-
         if index == 0 {
             println!("bin_shift\t{}", ::dynamic_scaling_mechanism::BIN_SHIFT);
 
@@ -391,7 +373,6 @@ fn main() {
                 0, 2_000_000_000, duration_ns - 2_000_000_000, duration_ns,
                 250_000_000);
 
-        let mut control_sequence = 0;
         let mut control_input = Some(control_input);
         if index != 0 {
             control_input.take().unwrap().close();
@@ -399,12 +380,7 @@ fn main() {
             let control_input = control_input.as_mut().unwrap();
             if instructions.get(0).map_or(false, |(ts, _)| *ts == 0) {
                 let (_ts, ctrl_instructions) = instructions.remove(0);
-                let count = ctrl_instructions.len();
-
-                for instruction in ctrl_instructions {
-                    control_input.send(Control::new(control_sequence, count, instruction));
-                }
-                control_sequence += 1;
+                control_input.send(ctrl_instructions).expect("plans are validated");
             }
         }
 
@@ -428,9 +404,8 @@ fn main() {
 
         let timer = ::std::time::Instant::now();
 
-        let mut last_migrated = None;
-
-        let mut did_migrate = false;
+        // The sequence number and start time of the migration in progress
+        let mut migrating = None;
 
         loop {
 
@@ -442,29 +417,21 @@ fn main() {
             let elapsed_ns = timer.elapsed().to_nanos();
 
             if index == 0 {
-                // was a migration supplied and the time captured? Is the migration definitely over?
-                if let Some(last_migrated) = last_migrated {
-                    if did_migrate && probe.with_frontier(|f| !f.less_than(&(last_migrated as usize + count))) {
-                        println!("migration_done\t{}\t{}", elapsed_ns, elapsed_ns - last_migrated);
-                        did_migrate = false;
+                // Is the migration in progress complete on all workers?
+                if let Some((sequence, started_ns)) = migrating {
+                    if migration_probe.is_complete(sequence) {
+                        println!("migration_done\t{}\t{}", elapsed_ns, elapsed_ns - started_ns);
+                        migrating = None;
                     }
                 }
                 if let Some(control_input) = control_input.as_mut() {
-                    if last_migrated.map_or(true, |time| probe.with_frontier(|f| !f.less_equal(&(time as usize + count)))) {
+                    if migrating.is_none() {
                         if instructions.get(0).map(|&(ts, _)| ts as usize + count <= *control_input.time()).unwrap_or(false) {
                             let (_ts, ctrl_instructions) = instructions.remove(0);
 
                             println!("control_time\t{}", control_input.time() - count);
 
-                            let count = ctrl_instructions.len();
-                            for instruction in ctrl_instructions {
-                                control_input.send(Control::new(control_sequence, count, instruction));
-                            }
-
-                            control_sequence += 1;
-                            last_migrated = Some(elapsed_ns);
-                            // Mark that we supplied migration instructions, will be picked up further down
-                            did_migrate = true;
+                            migrating = Some((control_input.send(ctrl_instructions).expect("plans are validated"), elapsed_ns));
                         }
                     }
                 }
@@ -13,6 +13,7 @@ extern crate fnv;
 
 pub mod config;
 pub mod event;
+pub mod plan;
 pub mod tools;
 
 pub mod queries;
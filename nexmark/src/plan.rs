@@ -0,0 +1,262 @@
+//! Migration plans.
+//!
+//! A migration plan describes the configurations a computation passes through, and when. Plans are
+//! JSON documents of the following form:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "steps": [
+//!     { "at_ns": 0, "map": [0, 1, 0, 1] },
+//!     { "at_ns": 10000000000, "moves": [[0, 1], [3, 0]] },
+//!     { "at_ns": 20000000000, "map": [0, 0, 1, 1], "strategy": "fluid" }
+//!   ]
+//! }
+//! ```
+//!
+//! Each step happens at `at_ns` nanoseconds into the experiment and provides either a complete
+//! `map` assigning bin `i` to worker `map[i]`, or a list of `moves` of `[bin, worker]` pairs.
+//! Maps must list all `1 << BIN_SHIFT` bins. The optional `strategy` determines how a map is
+//! installed: `sudden` (the default) installs it at once, `fluid` moves one bin at a time and
+//! requires a preceding step to compute the difference from. Steps must be ordered by time.
+//!
+//! Plans in the legacy text format are still accepted. Each line contains `M ts w_0 w_1 ...` to
+//! install a map or `D ts b_0 w_0 b_1 w_1 ...` to move bins at time `ts`.
+//!
+//! Errors in either format are reported with the line they originate from.
+use std::io::{BufRead, Read};
+
+use dynamic_scaling_mechanism::{BinId, ControlInst};
+
+/// The plan format version understood by this module.
+pub const PLAN_VERSION: u64 = 1;
+
+/// How a map is installed.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// Install the map in a single configuration.
+    Sudden,
+    /// Move one bin at a time, each in its own configuration.
+    Fluid,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::Sudden
+    }
+}
+
+/// A single step of a migration plan.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    /// Time of the step in nanoseconds since the start of the experiment.
+    pub at_ns: u64,
+    /// A complete map from bins to workers.
+    #[serde(default)]
+    pub map: Option<Vec<usize>>,
+    /// Pairs of bin and destination worker.
+    #[serde(default)]
+    pub moves: Option<Vec<(usize, usize)>>,
+    /// How to install `map`.
+    #[serde(default)]
+    pub strategy: Strategy,
+}
+
+/// A versioned migration plan.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    /// The format version, must equal `PLAN_VERSION`.
+    pub version: u64,
+    /// The steps of the plan, ordered by time.
+    pub steps: Vec<Step>,
+    /// The line each step starts at, if the plan was read from a document.
+    #[serde(skip)]
+    lines: Vec<usize>,
+}
+
+impl Plan {
+    /// Parse a plan from JSON, reporting the position of syntax errors.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, String> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text).map_err(|e| e.to_string())?;
+        let mut plan: Plan = ::serde_json::from_str(&text)
+            .map_err(|e| format!("line {}, column {}: {}", e.line(), e.column(), e))?;
+        if plan.version != PLAN_VERSION {
+            return Err(format!("unsupported plan version {}, expected {}", plan.version, PLAN_VERSION));
+        }
+        plan.lines = step_lines(&text);
+        Ok(plan)
+    }
+
+    /// Describe where step `index` is defined.
+    fn location(&self, index: usize) -> String {
+        match self.lines.get(index) {
+            Some(line) => format!("line {}", line),
+            None => format!("step {}", index),
+        }
+    }
+
+    /// Compile the plan into timed batches of control instructions for `peers` workers.
+    pub fn instructions(&self, peers: usize) -> Result<Vec<(u64, Vec<ControlInst>)>, String> {
+        let mut instructions = Vec::new();
+        // The step each configuration originates from
+        let mut origins = Vec::new();
+        let mut current: Option<Vec<usize>> = None;
+        for (index, step) in self.steps.iter().enumerate() {
+            let error = |message: String| format!("{}: {}", self.location(index), message);
+            match (&step.map, &step.moves) {
+                (Some(map), None) => {
+                    match step.strategy {
+                        Strategy::Sudden => instructions.push((step.at_ns, vec![ControlInst::Map(map.clone())])),
+                        Strategy::Fluid => {
+                            let previous = current.as_ref().ok_or_else(|| error("fluid strategy requires a preceding step".to_string()))?;
+                            if previous.len() != map.len() {
+                                return Err(error(format!("expected {} bins, found {}", previous.len(), map.len())));
+                            }
+                            for (bin, (&old, &new)) in previous.iter().zip(map.iter()).enumerate() {
+                                if old != new {
+                                    instructions.push((step.at_ns, vec![ControlInst::Move(BinId::new(bin), new)]));
+                                }
+                            }
+                        },
+                    }
+                    current = Some(map.clone());
+                },
+                (None, Some(moves)) => {
+                    if step.strategy != Strategy::Sudden {
+                        return Err(error("strategies only apply to maps".to_string()));
+                    }
+                    if let Some(current) = current.as_mut() {
+                        for &(bin, worker) in moves {
+                            if bin < current.len() {
+                                current[bin] = worker;
+                            }
+                        }
+                    }
+                    instructions.push((step.at_ns, moves.iter().map(|&(bin, worker)| ControlInst::Move(BinId::new(bin), worker)).collect()));
+                },
+                _ => return Err(error("expected exactly one of `map` and `moves`".to_string())),
+            }
+            origins.resize(instructions.len(), index);
+        }
+        check(&instructions, peers)
+            .map_err(|(index, message)| format!("{}: {}", self.location(origins[index]), message))?;
+        Ok(instructions)
+    }
+}
+
+/// Parse and validate a plan in the legacy text format for `peers` workers, reporting errors with
+/// their line number.
+pub fn read_text<R: BufRead>(reader: R, peers: usize) -> Result<Vec<(u64, Vec<ControlInst>)>, String> {
+    let (lines, instructions): (Vec<_>, Vec<_>) = parse_text_lines(reader)?.into_iter().unzip();
+    check(&instructions, peers).map_err(|(index, message)| format!("line {}: {}", lines[index], message))?;
+    Ok(instructions)
+}
+
+/// Parse a plan in the legacy text format into timed instructions and the lines they appear on.
+fn parse_text_lines<R: BufRead>(reader: R) -> Result<Vec<(usize, (u64, Vec<ControlInst>))>, String> {
+    let mut instructions = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let error = |message: String| format!("line {}: {}", index + 1, message);
+        let line = line.map_err(|e| error(e.to_string()))?;
+        let mut parts = line.split_whitespace();
+        let indicator = match parts.next() {
+            Some(indicator) => indicator,
+            None => continue,
+        };
+        let ts: u64 = parts.next()
+            .ok_or_else(|| error("missing time stamp".to_string()))?
+            .parse().map_err(|e| error(format!("failed to parse time stamp: {}", e)))?;
+        let numbers = parts
+            .map(|x| x.parse::<usize>().map_err(|e| error(format!("failed to parse {:?}: {}", x, e))))
+            .collect::<Result<Vec<_>, _>>()?;
+        match indicator {
+            "M" => instructions.push((index + 1, (ts, vec![ControlInst::Map(numbers)]))),
+            "D" => {
+                if numbers.len() % 2 != 0 {
+                    return Err(error("expected pairs of bin and worker".to_string()));
+                }
+                instructions.push((index + 1, (ts, numbers.chunks(2).map(|x| ControlInst::Move(BinId::new(x[0]), x[1])).collect())));
+            },
+            other => return Err(error(format!("unknown indicator {:?}", other))),
+        }
+    }
+    Ok(instructions)
+}
+
+/// Find the lines at which the steps of a JSON plan start.
+///
+/// Only applies to plans that parsed successfully: each object directly within the array that is
+/// the value of the top-level `"steps"` member is a step.
+fn step_lines(text: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let mut line = 1;
+    // The open objects and arrays enclosing the current position
+    let mut nesting = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    // The most recent string and member name in the top-level object
+    let mut string = String::new();
+    let mut member = String::new();
+    // Whether the open top-level array is the value of `"steps"`
+    let mut in_steps = false;
+    for c in text.chars() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+                continue;
+            }
+            if nesting.len() == 1 {
+                string.push(c);
+            }
+            continue;
+        }
+        match c {
+            '\n' => line += 1,
+            '"' => {
+                in_string = true;
+                string.clear();
+            },
+            ':' if nesting.len() == 1 => member = ::std::mem::replace(&mut string, String::new()),
+            '{' | '[' => {
+                if c == '[' && nesting == ['{'] {
+                    in_steps = member == "steps";
+                }
+                if c == '{' && in_steps && nesting == ['{', '['] {
+                    lines.push(line);
+                }
+                nesting.push(c);
+            },
+            '}' | ']' => { nesting.pop(); },
+            _ => {},
+        }
+    }
+    lines
+}
+
+/// Check that timed instructions are ordered by time and refer to existing bins and workers,
+/// returning the index of the first invalid configuration together with the error.
+fn check(instructions: &[(u64, Vec<ControlInst>)], peers: usize) -> Result<(), (usize, String)> {
+    let mut last = 0;
+    for (index, &(ts, ref batch)) in instructions.iter().enumerate() {
+        let error = |message: String| (index, message);
+        if ts < last {
+            return Err(error(format!("out of order, previous configuration at {}ns", last)));
+        }
+        last = ts;
+        if batch.is_empty() {
+            return Err(error("no instructions".to_string()));
+        }
+        for inst in batch {
+            inst.validate(peers).map_err(&error)?;
+        }
+    }
+    Ok(())
+}
@@ -4,11 +4,18 @@ use ::timely::dataflow::{Scope, Stream};
 use timely::dataflow::operators::Map;
 
 use dynamic_scaling_mechanism::operator::StatefulOperator;
+use dynamic_scaling_mechanism::topk::TopK;
 use ::event::Date;
 use ::calculate_hash;
 
 use {queries::NexmarkInput, queries::NexmarkTimer};
 
+/// Hot items: reports the auction with the most bids in the sliding window, for each time at which
+/// the bid counts change.
+///
+/// Unlike `q5`, which reports the hottest auction of each worker, the result is global: the counts
+/// are merged by `stateful_top_k`, which produces at most one auction per time, and only on worker
+/// 0. The output does not depend on the placement of bins.
 pub fn q5_flex<S: Scope<Timestamp=usize>>(input: &NexmarkInput, nt: NexmarkTimer, scope: &mut S, window_slice_count: usize, window_slide_ns: usize) -> Stream<S, usize>
 {
     let control = input.control(scope);
@@ -32,28 +39,36 @@ pub fn q5_flex<S: Scope<Timestamp=usize>>(input: &NexmarkInput, nt: NexmarkTimer
             not.notify_at_data(cap, nt.from_nexmark_time(Date::new(*a_time + window_slice_count * window_slide_ns)), InsDel::Del(auction));
         }
     }, move |cap, data, bid_bin, output| {
-        // Process additions (if any)
-//        println!("data.len(): {:?}", data.len());
+        // Process additions and deletions (if any), tracking changed auctions
+        let mut changed = Vec::new();
+        let bid_state: &mut HashMap<_, _> = bid_bin.state();
         for (_time, action) in data.drain(..) {
             match action {
                 InsDel::Ins(auction) => {
-                    // Stash pending deletions
-                    let bid_state: &mut HashMap<_, _> = bid_bin.state();
-                    let slot = bid_state.entry(auction).or_insert(0);
-                    *slot += 1;
+                    *bid_state.entry(auction).or_insert(0) += 1;
+                    changed.push(auction);
                 },
                 InsDel::Del(auction) => {
-                    let slot = bid_bin.state().entry(auction).or_insert(0);
-                    *slot -= 1;
+                    *bid_state.entry(auction).or_insert(0) -= 1;
+                    changed.push(auction);
                 }
             }
         }
-        bid_bin.state().retain(|_k, v| *v != 0);
-        // Output results (if any)
-        // TODO: This only accumulates per *bin*, not globally!
-        if let Some((auction, _count)) = bid_bin.state().iter().max_by_key(|(_auction_id, count)| *count) {
-            let mut session = output.session(&cap);
-            session.give(*auction);
+        changed.sort();
+        changed.dedup();
+        // Report updated counts, removing auctions without bids
+        let mut session = output.session(&cap);
+        for auction in changed {
+            match bid_state.get(&auction).cloned() {
+                Some(0) => {
+                    bid_state.remove(&auction);
+                    session.give((auction, None));
+                },
+                count => session.give((auction, count)),
+            }
         }
     })
+        // Select the hottest auction across all bins
+        .stateful_top_k(1, &control)
+        .flat_map(|top| top.into_iter().next().map(|(auction, _count)| auction))
 }
@@ -29,6 +29,7 @@ impl ::std::str::FromStr for ExperimentMapMode {
 }
 
 impl ExperimentMapMode {
+    /// Compute the validated control instructions for `peers` workers, see `plan` for the file format.
     pub fn instructions(&self, peers: usize, duration_ns: u64) -> Result<Vec<(u64, Vec<ControlInst>)>, String> {
         match self {
             ExperimentMapMode::None => {
@@ -76,27 +77,14 @@ impl ExperimentMapMode {
                 Ok(configurations)
             },
             ExperimentMapMode::File(migrations_file) => {
-                let f = ::std::fs::File::open(migrations_file).map_err(|e| e.to_string())?;
+                let f = ::std::fs::File::open(migrations_file).map_err(|e| format!("{}: {}", migrations_file, e))?;
                 let file = ::std::io::BufReader::new(&f);
-                use ::std::io::BufRead;
-                let mut instructions = Vec::new();
-                for line in file.lines() {
-                    let line = line.map_err(|e| e.to_string())?;
-                    let mut parts = line.split_whitespace();
-                    let indicator = parts.next().expect("Missing map/diff indicator");
-                    let ts: u64 = parts.next().expect("missing time stamp").parse().expect("Failed to parse time stamp");
-                    let instr = match indicator {
-                        "M" => (ts, vec![ControlInst::Map(parts.map(|x| x.parse().expect("Failed to parse parts")).collect())]),
-                        "D" => {
-                            let parts: Vec<usize> = parts.map(|x| x.parse().unwrap()).collect();
-                            let inst = parts.chunks(2).map(|x| ControlInst::Move(::dynamic_scaling_mechanism::BinId::new(x[0]), x[1])).collect();
-                            (ts, inst)
-                        },
-                        _ => return Err("Incorrect input found in map file".to_string()),
-                    };
-                    instructions.push(instr);
-                }
-                Ok(instructions)
+                let instructions = if migrations_file.ends_with(".json") {
+                    ::plan::Plan::from_reader(file).and_then(|plan| plan.instructions(peers))
+                } else {
+                    ::plan::read_text(file, peers)
+                };
+                instructions.map_err(|e| format!("{}: {}", migrations_file, e))
             },
 //            _ => panic!("unsupported map mode"),
         }
@@ -0,0 +1,99 @@
+extern crate nexmark;
+extern crate dynamic_scaling_mechanism;
+
+use dynamic_scaling_mechanism::{BinId, BIN_SHIFT, ControlInst};
+use nexmark::plan::{self, Plan};
+
+/// A map of all bins, assigning bin `i` to worker `i % peers`.
+fn map_json(peers: usize) -> String {
+    (0..1 << BIN_SHIFT).map(|bin| (bin % peers).to_string()).collect::<Vec<_>>().join(", ")
+}
+
+#[test]
+fn json_plan() {
+    let text = format!(r#"{{
+  "version": 1,
+  "steps": [
+    {{ "at_ns": 0, "map": [{}] }},
+    {{ "at_ns": 10, "moves": [[0, 1], [3, 0]] }},
+    {{ "at_ns": 20, "map": [{}], "strategy": "fluid" }}
+  ]
+}}"#, map_json(2), map_json(1));
+    let instructions = Plan::from_reader(text.as_bytes()).unwrap().instructions(2).unwrap();
+
+    let mut expected = vec![
+        (0, vec![ControlInst::Map((0..1 << BIN_SHIFT).map(|bin| bin % 2).collect())]),
+        (10, vec![ControlInst::Move(BinId::new(0), 1), ControlInst::Move(BinId::new(3), 0)]),
+    ];
+    // Bin 0 and the odd bins but 3 are on worker 1
+    expected.push((20, vec![ControlInst::Move(BinId::new(0), 0)]));
+    for bin in (1..1 << BIN_SHIFT).filter(|&bin| bin % 2 == 1 && bin != 3) {
+        expected.push((20, vec![ControlInst::Move(BinId::new(bin), 0)]));
+    }
+    assert_eq!(expected, instructions);
+}
+
+#[test]
+fn json_plan_version_mismatch() {
+    let text = r#"{ "version": 2, "steps": [] }"#;
+    let error = Plan::from_reader(text.as_bytes()).unwrap_err();
+    assert!(error.contains("unsupported plan version 2"), "{}", error);
+}
+
+#[test]
+fn json_plan_syntax_error() {
+    let text = "{\n  \"version\": 1,\n  \"steps\": [\n    { \"at_ns\": 0 \"moves\": [] }\n  ]\n}";
+    let error = Plan::from_reader(text.as_bytes()).unwrap_err();
+    assert!(error.starts_with("line 4, column"), "{}", error);
+}
+
+#[test]
+fn json_plan_worker_out_of_range() {
+    let text = r#"{
+  "version": 1,
+  "steps": [
+    { "at_ns": 0, "moves": [[0, 1]] },
+    { "at_ns": 10, "moves": [[1, 0],
+                              [2, 5]] },
+    { "at_ns": 20, "moves": [[2, 1]] }
+  ]
+}"#;
+    let plan = Plan::from_reader(text.as_bytes()).unwrap();
+    assert!(plan.instructions(6).is_ok());
+    let error = plan.instructions(2).unwrap_err();
+    assert!(error.starts_with("line 5: "), "{}", error);
+    assert!(error.contains("worker 5"), "{}", error);
+}
+
+#[test]
+fn json_plan_step_error() {
+    let text = r#"{
+  "version": 1,
+  "steps": [
+    { "at_ns": 0, "moves": [[0, 1]], "strategy": "fluid" }
+  ]
+}"#;
+    let error = Plan::from_reader(text.as_bytes()).unwrap().instructions(2).unwrap_err();
+    assert!(error.starts_with("line 4: "), "{}", error);
+}
+
+#[test]
+fn text_plan() {
+    let text = format!("M 0 {}\n\nD 10 0 1 3 0\n", map_json(2).replace(",", ""));
+    let instructions = plan::read_text(text.as_bytes(), 2).unwrap();
+    let expected = vec![
+        (0, vec![ControlInst::Map((0..1 << BIN_SHIFT).map(|bin| bin % 2).collect())]),
+        (10, vec![ControlInst::Move(BinId::new(0), 1), ControlInst::Move(BinId::new(3), 0)]),
+    ];
+    assert_eq!(expected, instructions);
+}
+
+#[test]
+fn text_plan_errors() {
+    let error = plan::read_text("D 0 0 1\n\nD 10 1 2\n".as_bytes(), 2).unwrap_err();
+    assert!(error.starts_with("line 3: "), "{}", error);
+    let error = plan::read_text("D 10 0 1\nD 5 1 0\n".as_bytes(), 2).unwrap_err();
+    assert!(error.starts_with("line 2: out of order"), "{}", error);
+    let error = plan::read_text("D 0 0\n".as_bytes(), 2).unwrap_err();
+    assert!(error.starts_with("line 1: "), "{}", error);
+}
@@ -0,0 +1,104 @@
+//! Migratable keyed aggregation with pre-exchange combiners, implemented with Megaphone.
+use std::collections::hash_map::Entry;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
+
+use timely::ExchangeData;
+use timely::dataflow::{Stream, Scope};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::channels::pushers::Tee;
+use timely::dataflow::operators::{Capability, FrontierNotificator, Operator};
+use timely::dataflow::operators::generic::OutputHandle;
+use timely::order::TotalOrder;
+use timely::progress::Timestamp;
+
+use operator::StatefulOperator;
+use ::{calculate_hash, Control};
+
+/// Provide a keyed aggregation operator that combines values before they are exchanged.
+pub trait StatefulAggregate<S, K, V>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Eq,
+{
+    /// Aggregates values per key using `combine`, reporting `(key, aggregate)` once per time for
+    /// each key that received values at that time.
+    ///
+    /// `combine` folds a value into an aggregate and must be associative and commutative: values are
+    /// first combined per key and time on the worker that produced them, and only the partial
+    /// aggregates are routed to the worker owning the key's bin, where they are combined into the
+    /// bin's state.
+    fn stateful_aggregate<F: Fn(&mut V, V)+'static>(&self, combine: F, control: &Stream<S, Control>) -> Stream<S, (K, V)>;
+}
+
+impl<S, K, V> StatefulAggregate<S, K, V> for Stream<S, (K, V)>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Eq,
+{
+    fn stateful_aggregate<F: Fn(&mut V, V)+'static>(&self, combine: F, control: &Stream<S, Control>) -> Stream<S, (K, V)> {
+        let combine = Rc::new(combine);
+        let pre_combine = Rc::clone(&combine);
+
+        let combined = self.unary_frontier(Pipeline, "AggregateCombine", move |_cap, _info| {
+            let mut notificator = FrontierNotificator::new();
+            let mut stash: HashMap<S::Timestamp, HashMap<K, V>> = Default::default();
+            let mut data_buffer = Vec::new();
+            move |input, output| {
+                input.for_each(|time, data| {
+                    data.swap(&mut data_buffer);
+                    let partials = stash.entry(time.time().clone()).or_insert_with(Default::default);
+                    for (key, value) in data_buffer.drain(..) {
+                        combine_into(partials, key, value, &*pre_combine);
+                    }
+                    notificator.notify_at(time.retain());
+                });
+                while let Some(time) = notificator.next(&[input.frontier()]) {
+                    if let Some(mut partials) = stash.remove(time.time()) {
+                        output.session(&time).give_iterator(partials.drain());
+                    }
+                }
+            }
+        });
+
+        let mut updated = HashSet::default();
+        combined.stateful_unary(control, |(key, _value)| calculate_hash(key), "Aggregate", move |cap, data, bin, output| {
+            let states: &mut HashMap<_, _> = bin.state();
+            let mut session_cap = cap.clone();
+            for (time, (key, value)) in data.drain(..) {
+                if *session_cap.time() != time {
+                    report(&mut updated, states, &session_cap, output);
+                    session_cap = cap.delayed(&time);
+                }
+                combine_into(states, key.clone(), value, &*combine);
+                updated.insert(key);
+            }
+            report(&mut updated, states, &session_cap, output);
+        })
+    }
+}
+
+/// Combines `value` into the aggregate for `key`, or inserts it if there is none.
+fn combine_into<K: Hash+Eq, V, F: Fn(&mut V, V)>(aggregates: &mut HashMap<K, V>, key: K, value: V, combine: &F) {
+    match aggregates.entry(key) {
+        Entry::Occupied(mut entry) => combine(entry.get_mut(), value),
+        Entry::Vacant(entry) => { entry.insert(value); },
+    }
+}
+
+/// Reports the aggregates of the `updated` keys at the time of `cap`.
+fn report<T: Timestamp, K: ExchangeData+Hash+Eq, V: ExchangeData>(updated: &mut HashSet<K>, aggregates: &HashMap<K, V>, cap: &Capability<T>, output: &mut OutputHandle<T, (K, V), Tee<T, (K, V)>>) {
+    if !updated.is_empty() {
+        let mut session = output.session(cap);
+        for key in updated.drain() {
+            let aggregate = aggregates[&key].clone();
+            session.give((key, aggregate));
+        }
+    }
+}
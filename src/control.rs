@@ -0,0 +1,438 @@
+//! Sources of control instructions.
+//!
+//! A [`ControlHandle`] feeds batches of instructions into a dataflow from the worker's code. It
+//! assigns sequence numbers and counts, lets only the leader worker send, and broadcasts the
+//! resulting control stream to all workers.
+//!
+//! A control source reads migration instructions at runtime from a local endpoint, such that an
+//! external orchestrator can trigger migrations in a running computation. Each line received on the
+//! endpoint describes one batch of instructions, which is applied atomically:
+//!
+//! * `M w_0 w_1 ... w_n` installs a new map, assigning bin `i` to worker `w_i`.
+//! * `D b_0 w_0 b_1 w_1 ...` moves bin `b_i` to worker `w_i`.
+//! * `A s_0 s_1 ...` withdraws the pending configurations with sequence numbers `s_i`.
+//!
+//! Empty lines and lines starting with `#` are ignored. Malformed lines are reported on standard
+//! error and skipped.
+//!
+//! Alternatively, [`SplitControl`] extracts control instructions embedded in a data stream.
+//!
+//! [`ControlHandle`]: struct.ControlHandle.html
+//! [`SplitControl`]: trait.SplitControl.html
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use timely::Data;
+use timely::communication::Allocate;
+use timely::dataflow::{InputHandle, Stream, Scope};
+use timely::dataflow::channels::pact::{Exchange, Pipeline};
+use timely::dataflow::operators::{Broadcast, FrontierNotificator, Operator};
+use timely::dataflow::operators::generic::builder_rc::OperatorBuilder;
+use timely::dataflow::scopes::Child;
+use timely::order::{PartialOrder, TotalOrder};
+use timely::progress::Timestamp;
+use timely::worker::Worker;
+
+use fnv::FnvHashMap as HashMap;
+
+use ::{BinId, Control, ControlInst};
+
+/// An input for batches of control instructions.
+///
+/// All workers construct the handle and its stream, but only the leader, worker 0, sends
+/// instructions. Sends on other workers only advance the sequence number, so the same code can run
+/// on all workers. Invalid batches are rejected when sent and never reach the stateful operators.
+///
+/// #Examples
+/// ```
+/// extern crate timely;
+/// extern crate dynamic_scaling_mechanism;
+///
+/// use timely::dataflow::operators::Inspect;
+/// use dynamic_scaling_mechanism::{BinId, ControlInst};
+/// use dynamic_scaling_mechanism::control::ControlHandle;
+///
+/// fn main() {
+///     timely::example(|scope| {
+///         let mut control = ControlHandle::new();
+///         control.to_stream(scope).inspect(|c| println!("{:?}", c));
+///         assert_eq!(Ok(0), control.send_at(1, vec![ControlInst::Move(BinId::new(0), 0)]));
+///         // There is no worker 5, the batch is rejected
+///         assert!(control.send_at(2, vec![ControlInst::Move(BinId::new(1), 5)]).is_err());
+///         assert_eq!(Ok(1), control.send_at(3, vec![ControlInst::Move(BinId::new(1), 0)]));
+///     });
+/// }
+/// ```
+pub struct ControlHandle<T: Timestamp> {
+    input: InputHandle<T, Control>,
+    leader: bool,
+    peers: usize,
+    sequence: u64,
+    last_time: Option<T>,
+}
+
+impl<T: Timestamp> ControlHandle<T> {
+    /// Construct a new control handle.
+    pub fn new() -> Self {
+        ControlHandle {
+            input: InputHandle::new(),
+            leader: false,
+            peers: 0,
+            sequence: 0,
+            last_time: None,
+        }
+    }
+
+    /// Create the broadcast control stream in `scope`. Must be called exactly once.
+    pub fn to_stream<'a, A: Allocate>(&mut self, scope: &mut Child<'a, Worker<A>, T>) -> Stream<Child<'a, Worker<A>, T>, Control> {
+        self.leader = scope.index() == 0;
+        self.peers = scope.peers();
+        self.input.to_stream(scope).broadcast()
+    }
+
+    /// Returns `true` if this worker sends instructions.
+    pub fn is_leader(&self) -> bool {
+        self.leader
+    }
+
+    /// The current time of the handle.
+    pub fn time(&self) -> &T {
+        self.input.time()
+    }
+
+    /// Advance the time of the handle, allowing configurations at earlier times to take effect.
+    pub fn advance_to(&mut self, time: T) {
+        self.input.advance_to(time);
+    }
+
+    /// Send a batch of instructions at the current time, returning its sequence number.
+    ///
+    /// Rejects the batch without assigning a sequence number if it is empty, if a batch was already
+    /// sent at the current time, or if an instruction refers to a worker or bin that does not
+    /// exist. All workers reject the same batches, such that sequence numbers stay in agreement.
+    pub fn send(&mut self, batch: Vec<ControlInst>) -> Result<u64, String> {
+        if batch.is_empty() {
+            return Err("control batches must not be empty".to_string());
+        }
+        if self.last_time.as_ref() == Some(self.input.time()) {
+            return Err(format!("a control batch was already sent at {:?}", self.input.time()));
+        }
+        validate_batch(&batch, self.peers)?;
+        self.last_time = Some(self.input.time().clone());
+        let sequence = self.sequence;
+        self.sequence += 1;
+        if self.leader {
+            let count = batch.len();
+            for inst in batch {
+                self.input.send(Control::new(sequence, count, inst));
+            }
+        }
+        Ok(sequence)
+    }
+
+    /// Advance to `time`, unless the handle is already beyond it, and send a batch of instructions.
+    /// Returns the batch's sequence number, or the reason the batch was rejected.
+    pub fn send_at(&mut self, time: T, batch: Vec<ControlInst>) -> Result<u64, String> {
+        if self.input.time().less_than(&time) {
+            self.input.advance_to(time);
+        }
+        self.send(batch)
+    }
+
+    /// Close the handle, no further instructions can be sent.
+    pub fn close(self) {
+        self.input.close();
+    }
+}
+
+impl<T: Timestamp> Default for ControlHandle<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A local endpoint to read control instructions from.
+#[derive(Clone, Debug)]
+pub enum ControlEndpoint {
+    /// Listen on a Unix domain socket at the given path. Connections are served one at a time. A
+    /// socket left at the path, e.g. by an earlier run, is replaced.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// Read from an existing named pipe at the given path, re-opening it whenever the writer closes.
+    #[cfg(unix)]
+    Fifo(PathBuf),
+    /// Listen on the given TCP port on localhost. Connections are served one at a time.
+    Tcp(u16),
+}
+
+impl ControlEndpoint {
+    /// Open the endpoint and spawn a thread that forwards parsed batches of instructions.
+    ///
+    /// The thread terminates once the receiver is dropped and further input arrives.
+    pub fn listen(&self) -> io::Result<Receiver<Vec<ControlInst>>> {
+        let (sender, receiver) = channel();
+        match *self {
+            #[cfg(unix)]
+            ControlEndpoint::Unix(ref path) => {
+                use std::os::unix::fs::FileTypeExt;
+                // Binding fails if the path exists, but only remove sockets
+                match ::std::fs::symlink_metadata(path) {
+                    Ok(ref metadata) if metadata.file_type().is_socket() => ::std::fs::remove_file(path)?,
+                    _ => {},
+                }
+                let listener = ::std::os::unix::net::UnixListener::bind(path)?;
+                thread::spawn(move || {
+                    for connection in listener.incoming() {
+                        match connection {
+                            Ok(connection) => if forward(connection, &sender).is_err() { break },
+                            Err(e) => eprintln!("control: failed to accept connection: {}", e),
+                        }
+                    }
+                });
+            },
+            #[cfg(unix)]
+            ControlEndpoint::Fifo(ref path) => {
+                let path = path.clone();
+                // Fail early if the pipe cannot be accessed
+                ::std::fs::metadata(&path)?;
+                thread::spawn(move || {
+                    loop {
+                        // Opening blocks until a writer connects
+                        match ::std::fs::File::open(&path) {
+                            Ok(file) => if forward(file, &sender).is_err() { break },
+                            Err(e) => {
+                                eprintln!("control: failed to open {}: {}", path.display(), e);
+                                break;
+                            },
+                        }
+                    }
+                });
+            },
+            ControlEndpoint::Tcp(port) => {
+                let listener = TcpListener::bind(("127.0.0.1", port))?;
+                thread::spawn(move || {
+                    for connection in listener.incoming() {
+                        match connection {
+                            Ok(connection) => if forward(connection, &sender).is_err() { break },
+                            Err(e) => eprintln!("control: failed to accept connection: {}", e),
+                        }
+                    }
+                });
+            },
+        }
+        Ok(receiver)
+    }
+}
+
+/// Forward the batches read from `reader` until it is exhausted. Fails if the receiver is gone.
+fn forward<R: Read>(reader: R, sender: &Sender<Vec<ControlInst>>) -> Result<(), ()> {
+    for line in BufReader::new(reader).lines() {
+        match line {
+            Ok(line) => match parse_instructions(&line) {
+                Ok(None) => {},
+                Ok(Some(batch)) => sender.send(batch).map_err(|_| ())?,
+                Err(e) => eprintln!("control: ignoring {:?}: {}", line, e),
+            },
+            Err(e) => {
+                eprintln!("control: read failed: {}", e);
+                break;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Parse a line of control input into a batch of instructions. Returns `None` for empty lines and
+/// comments.
+///
+/// #Examples
+/// ```
+/// use dynamic_scaling_mechanism::control::parse_instructions;
+///
+/// assert_eq!(None, parse_instructions("# comment").unwrap().map(|batch| batch.len()));
+/// assert_eq!(Some(2), parse_instructions("D 0 1 3 0").unwrap().map(|batch| batch.len()));
+/// assert!(parse_instructions("D 0").is_err());
+/// ```
+pub fn parse_instructions(line: &str) -> Result<Option<Vec<ControlInst>>, String> {
+    let mut parts = line.split_whitespace();
+    let indicator = match parts.next() {
+        None => return Ok(None),
+        Some(indicator) if indicator.starts_with('#') => return Ok(None),
+        Some(indicator) => indicator,
+    };
+    let numbers = parts
+        .map(|x| x.parse::<usize>().map_err(|e| format!("failed to parse {:?}: {}", x, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    match indicator {
+        "M" => {
+            if numbers.is_empty() {
+                return Err("empty map".to_string());
+            }
+            Ok(Some(vec![ControlInst::Map(numbers)]))
+        },
+        "D" => {
+            if numbers.is_empty() || numbers.len() % 2 != 0 {
+                return Err("expected pairs of bin and worker".to_string());
+            }
+            Ok(Some(numbers.chunks(2).map(|x| ControlInst::Move(BinId::new(x[0]), x[1])).collect()))
+        },
+        "A" => {
+            if numbers.is_empty() {
+                return Err("expected sequence numbers".to_string());
+            }
+            Ok(Some(numbers.into_iter().map(|sequence| ControlInst::Abort(sequence as u64)).collect()))
+        },
+        other => Err(format!("unknown indicator {:?}", other)),
+    }
+}
+
+/// Provides a control source that follows the progress of a stream.
+pub trait ControlSource<G: Scope> {
+    /// Read control instructions from `endpoint` and provide them as a control stream on all
+    /// workers.
+    ///
+    /// Only worker 0 opens the endpoint. Instructions are issued at the current time of this
+    /// stream's frontier, and the control stream's frontier follows it. Each batch receives the next
+    /// sequence number and a count of its instructions. At most one batch is issued per time, later
+    /// batches wait for the frontier to advance. Batches referring to workers or bins that do not
+    /// exist are reported on standard error and dropped.
+    ///
+    /// Panics if the endpoint cannot be opened.
+    fn control_source(&self, endpoint: &ControlEndpoint) -> Stream<G, Control>;
+}
+
+impl<G: Scope, D: Data> ControlSource<G> for Stream<G, D>
+    where
+        G::Timestamp: TotalOrder,
+{
+    fn control_source(&self, endpoint: &ControlEndpoint) -> Stream<G, Control> {
+        let peers = self.scope().peers();
+        let receiver = if self.scope().index() == 0 {
+            Some(endpoint.listen().unwrap_or_else(|e| panic!("Failed to open control endpoint {:?}: {}", endpoint, e)))
+        } else {
+            None
+        };
+
+        self.unary_frontier(Pipeline, "ControlSource", move |cap, _info| {
+            let mut cap = Some(cap);
+            let mut sequence = 0;
+            let mut last_time = None;
+            let mut batches = VecDeque::new();
+            move |input, output| {
+                // Only the frontier is of interest
+                input.for_each(|_time, _data| {});
+
+                if let Some(receiver) = receiver.as_ref() {
+                    while let Ok(batch) = receiver.try_recv() {
+                        // Reject invalid batches here rather than in the stateful operators
+                        match validate_batch(&batch, peers) {
+                            Ok(()) => batches.push_back(batch),
+                            Err(e) => eprintln!("control: ignoring {:?}: {}", batch, e),
+                        }
+                    }
+                }
+
+                if input.frontier().is_empty() {
+                    cap.take();
+                } else if let Some(cap) = cap.as_mut() {
+                    let time = input.frontier().frontier()[0].clone();
+                    if cap.time().less_than(&time) {
+                        cap.downgrade(&time);
+                    }
+                    if last_time.as_ref() != Some(cap.time()) {
+                        if let Some(batch) = batches.pop_front() {
+                            let count = batch.len();
+                            output.session(cap).give_iterator(batch.into_iter().map(|inst| Control::new(sequence, count, inst)));
+                            sequence += 1;
+                            last_time = Some(cap.time().clone());
+                        }
+                    }
+                }
+            }
+        }).broadcast()
+    }
+}
+
+/// Check that all instructions of `batch` are valid for `peers` workers.
+fn validate_batch(batch: &[ControlInst], peers: usize) -> Result<(), String> {
+    batch.iter().map(|inst| inst.validate(peers)).collect()
+}
+
+/// Extracts control instructions embedded in a data stream.
+pub trait SplitControl<G: Scope, D: Data> {
+    /// Split the stream into data records and a control stream built from the records for which
+    /// `extract` returns an instruction.
+    ///
+    /// All instructions extracted at the same time form one batch. Batches receive sequence
+    /// numbers in time order and are broadcast to all workers. The control stream's frontier
+    /// follows the frontier of this stream, such that stateful operators apply a batch at exactly
+    /// the time of the records it was extracted from, as if it had been sent on a separate control
+    /// stream. The order of instructions within a batch is unspecified. Batches containing an
+    /// invalid instruction are logged and dropped.
+    fn split_control<F: Fn(&D)->Option<ControlInst>+'static>(&self, extract: F) -> (Stream<G, D>, Stream<G, Control>);
+}
+
+impl<G: Scope, D: Data> SplitControl<G, D> for Stream<G, D>
+    where
+        G::Timestamp: TotalOrder,
+{
+    fn split_control<F: Fn(&D)->Option<ControlInst>+'static>(&self, extract: F) -> (Stream<G, D>, Stream<G, Control>) {
+        let mut builder = OperatorBuilder::new("SplitControl".into(), self.scope());
+        let mut input = builder.new_input(self, Pipeline);
+        let (mut data_out, data) = builder.new_output();
+        let (mut control_out, instructions) = builder.new_output();
+
+        builder.build(move |_capabilities| {
+            let mut data_buffer = Vec::new();
+            move |_frontiers| {
+                let mut data_out = data_out.activate();
+                let mut control_out = control_out.activate();
+                input.for_each(|time, data| {
+                    data.swap(&mut data_buffer);
+                    let mut data_session = data_out.session(&time);
+                    let mut control_session = control_out.session(&time);
+                    for datum in data_buffer.drain(..) {
+                        match extract(&datum) {
+                            Some(inst) => control_session.give(inst),
+                            None => data_session.give(datum),
+                        }
+                    }
+                });
+            }
+        });
+
+        // Sequence the instructions of each time into a batch on a single worker
+        let peers = self.scope().peers();
+        let control = instructions.unary_frontier(Exchange::new(|_| 0), "SequenceControl", |_cap, _info| {
+            let mut notificator = FrontierNotificator::new();
+            let mut stash: HashMap<G::Timestamp, Vec<ControlInst>> = Default::default();
+            let mut sequence = 0;
+            let mut data_buffer = Vec::new();
+            move |input, output| {
+                input.for_each(|time, data| {
+                    data.swap(&mut data_buffer);
+                    stash.entry(time.time().clone()).or_insert_with(Vec::new).extend(data_buffer.drain(..));
+                    notificator.notify_at(time.retain());
+                });
+                while let Some(time) = notificator.next(&[input.frontier()]) {
+                    if let Some(batch) = stash.remove(time.time()) {
+                        // Reject invalid batches here rather than in the stateful operators
+                        if let Err(e) = validate_batch(&batch, peers) {
+                            eprintln!("control: ignoring {:?}: {}", batch, e);
+                            continue;
+                        }
+                        let count = batch.len();
+                        output.session(&time).give_iterator(batch.into_iter().map(|inst| Control::new(sequence, count, inst)));
+                        sequence += 1;
+                    }
+                }
+            }
+        }).broadcast();
+
+        (data, control)
+    }
+}
@@ -0,0 +1,215 @@
+//! Migratable distinct and approximate count-distinct operators, implemented with Megaphone.
+use std::hash::Hash;
+
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
+
+use timely::ExchangeData;
+use timely::dataflow::{Stream, Scope};
+use timely::order::{PartialOrder, TotalOrder};
+use timely::progress::{PathSummary, Timestamp};
+
+use keyed::TimedState;
+use operator::StatefulOperator;
+use ::{calculate_hash, Control};
+
+/// Provide a distinct operator that can be migrated.
+pub trait StatefulDistinct<S, D>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder+ExchangeData,
+    D: ExchangeData+Hash+Eq,
+{
+    /// Reports each record the first time it is seen.
+    ///
+    /// Without a `ttl`, records are remembered forever. With a `ttl`, a record is forgotten once
+    /// it has not been seen for `ttl`, and is reported again on its next occurrence.
+    fn stateful_distinct(&self, ttl: Option<<S::Timestamp as Timestamp>::Summary>, control: &Stream<S, Control>) -> Stream<S, D>;
+}
+
+/// Notifications of the distinct operator.
+#[derive(Abomonation, Clone, Debug, Eq, PartialEq)]
+enum DistinctEvent<D> {
+    /// An occurrence of a record.
+    Record(D),
+    /// Forget a record, unless it was seen again since this was scheduled.
+    Expire(D),
+}
+
+impl<S, D> StatefulDistinct<S, D> for Stream<S, D>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder+ExchangeData,
+    D: ExchangeData+Hash+Eq,
+{
+    fn stateful_distinct(&self, ttl: Option<<S::Timestamp as Timestamp>::Summary>, control: &Stream<S, Control>) -> Stream<S, D> {
+        let mut data_buffer = vec![];
+        let mut timers = vec![];
+        let expiry = move |time: &S::Timestamp| ttl.as_ref().and_then(|ttl| ttl.results_in(time));
+
+        self.stateful_unary_input(control, |d| calculate_hash(d), "Distinct", move |state, cap, time, data, _output| {
+            data.swap(&mut data_buffer);
+            for (_worker, key_id, d) in data_buffer.drain(..) {
+                state.get(key_id).notificator().notify_at_data(&cap, time.clone(), DistinctEvent::Record(d));
+            }
+        }, move |cap, data, bin, output| {
+            // Records mapped to the time they were last seen
+            let seen: &mut TimedState<_, S::Timestamp> = bin.state();
+            let mut session_cap = cap.clone();
+            for (time, event) in data.drain(..) {
+                match event {
+                    DistinctEvent::Record(d) => {
+                        let live = seen.get(&d).map_or(false, |last| expiry(last).map_or(true, |e| time.less_than(&e)));
+                        if !live {
+                            if *session_cap.time() != time {
+                                session_cap = cap.delayed(&time);
+                            }
+                            output.session(&session_cap).give(d.clone());
+                        }
+                        let (timer_key, last) = seen.get_or_insert_with(&d, || time.clone());
+                        *last = time.clone();
+                        // Replaces the pending expiry of `d`, if any
+                        if let Some(e) = expiry(&time) {
+                            timers.push((timer_key, e, DistinctEvent::Expire(d)));
+                        }
+                    },
+                    DistinctEvent::Expire(d) => {
+                        let expired = seen.get(&d).map_or(false, |last| expiry(last).map_or(false, |e| e.less_equal(&time)));
+                        if expired {
+                            seen.remove(&d);
+                        }
+                    },
+                }
+            }
+            for (timer_key, time, event) in timers.drain(..) {
+                bin.notificator().notify_at_key(&cap, timer_key, time, event);
+            }
+        })
+    }
+}
+
+/// Provide an approximate count-distinct operator that can be migrated.
+pub trait StatefulCountDistinct<S, K, V>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Hash+Eq,
+{
+    /// Estimates the number of distinct values per key with a `HyperLogLog` sketch of the given
+    /// `precision`, reporting `(key, estimate)` once per time for each key that received values.
+    fn stateful_count_distinct(&self, precision: u8, control: &Stream<S, Control>) -> Stream<S, (K, u64)>;
+}
+
+impl<S, K, V> StatefulCountDistinct<S, K, V> for Stream<S, (K, V)>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Hash+Eq,
+{
+    fn stateful_count_distinct(&self, precision: u8, control: &Stream<S, Control>) -> Stream<S, (K, u64)> {
+        // Validate eagerly rather than on the first record
+        HyperLogLog::new(precision);
+        let mut updated = HashSet::default();
+        self.stateful_unary(control, |(key, _value)| calculate_hash(key), "CountDistinct", move |cap, data, bin, output| {
+            let sketches: &mut HashMap<_, HyperLogLog> = bin.state();
+            let mut session_cap = cap.clone();
+            for (time, (key, value)) in data.drain(..) {
+                if *session_cap.time() != time {
+                    let mut session = output.session(&session_cap);
+                    for key in updated.drain() {
+                        let estimate = sketches[&key].estimate();
+                        session.give((key, estimate));
+                    }
+                    session_cap = cap.delayed(&time);
+                }
+                sketches.entry(key.clone()).or_insert_with(|| HyperLogLog::new(precision)).insert(&value);
+                updated.insert(key);
+            }
+            let mut session = output.session(&session_cap);
+            for key in updated.drain() {
+                let estimate = sketches[&key].estimate();
+                session.give((key, estimate));
+            }
+        })
+    }
+}
+
+/// A HyperLogLog sketch to estimate the number of distinct elements in a multiset.
+///
+/// A sketch of precision `p` uses `2^p` one-byte registers and has a standard error of about
+/// `1.04 / sqrt(2^p)`.
+///
+/// #Examples
+/// ```
+/// use dynamic_scaling_mechanism::distinct::HyperLogLog;
+///
+/// let mut sketch = HyperLogLog::new(12);
+/// for i in 0..10_000u64 {
+///     sketch.insert(&(i % 1_000));
+/// }
+/// let estimate = sketch.estimate();
+/// assert!(estimate > 900 && estimate < 1_100, "estimate: {}", estimate);
+/// ```
+#[derive(Abomonation, Clone, Debug, Eq, PartialEq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// Construct an empty sketch with `2^precision` registers. `precision` must be in `4..=16`.
+    pub fn new(precision: u8) -> Self {
+        assert!(precision >= 4 && precision <= 16, "precision must be in 4..=16, found {}", precision);
+        Self { registers: vec![0; 1 << precision] }
+    }
+
+    fn precision(&self) -> u32 {
+        self.registers.len().trailing_zeros()
+    }
+
+    /// Add an element to the sketch.
+    pub fn insert<T: Hash>(&mut self, element: &T) {
+        // FNV does not mix high bits well, finalize with splitmix64
+        let mut hash = calculate_hash(element);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+        hash ^= hash >> 31;
+
+        let precision = self.precision();
+        let index = (hash >> (64 - precision)) as usize;
+        let rank = ((hash << precision).leading_zeros() + 1).min(64 - precision + 1) as u8;
+        if self.registers[index] < rank {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// Merge another sketch of the same precision into this sketch.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        assert_eq!(self.registers.len(), other.registers.len(), "Merging sketches of different precision");
+        for (register, other) in self.registers.iter_mut().zip(other.registers.iter()) {
+            if *register < *other {
+                *register = *other;
+            }
+        }
+    }
+
+    /// Estimate the number of distinct elements added to the sketch.
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            // Small range correction: linear counting
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
@@ -1,65 +1,242 @@
-//! General purpose state transition operator.
+//! Migratable joins on streams of `(key, value)` pairs, implemented with Megaphone.
+//!
+//! Both inputs are partitioned by the hash of their keys according to the same control stream,
+//! such that matching records reside in the same bin. Each side stores its records per key and
+//! probes the other side's records when new data arrives. How long records remain eligible for
+//! matching is determined by a `Retention` policy.
+use std::cmp::max;
 use std::hash::Hash;
 
 use fnv::FnvHashMap as HashMap;
 
 use timely::ExchangeData;
 use timely::dataflow::{Stream, Scope};
+use timely::dataflow::operators::Map;
+use timely::order::{PartialOrder, TotalOrder};
+use timely::progress::{PathSummary, Timestamp};
 
 use operator::StatefulOperator;
-use ::Bin;
+use ::{calculate_hash, Control};
 
-fn calculate_hash<T: Hash>(t: &T) -> u64 {
-    use ::std::hash::Hasher;
-    let mut h: ::fnv::FnvHasher = Default::default();
-    t.hash(&mut h);
-    h.finish()
+/// Describes for how long records are retained by a join.
+#[derive(Clone, Debug)]
+pub enum Retention<T: Timestamp> {
+    /// Records are retained forever. The state of the join grows without bounds.
+    Forever,
+    /// Records are retained for the provided duration. A record at time `t` matches records on
+    /// the other input with times in the open interval `(t - summary, t + summary)`, i.e. this
+    /// describes a time-windowed or interval join. Records are evicted once the frontier reaches
+    /// `t + summary`.
+    Within(T::Summary),
 }
 
-trait BinarySkeleton<S, K, V>
-    where
-        S: Scope, // The containing scope
-        S::Timestamp: ::timely::order::TotalOrder,
-        K: ExchangeData+Hash+Eq,
-        V: ExchangeData, // Input data
+impl<T: Timestamp> Retention<T> {
+    /// The time at which a record at `time` expires, if any.
+    fn expiry(&self, time: &T) -> Option<T> {
+        match *self {
+            Retention::Forever => None,
+            Retention::Within(ref summary) => summary.results_in(time),
+        }
+    }
+
+    /// Tests whether records at `time1` and `time2` are eligible to match.
+    fn matches(&self, time1: &T, time2: &T) -> bool {
+        let before = |time: &T, other: &T| self.expiry(other).map_or(true, |expiry| time.less_than(&expiry));
+        before(time1, time2) && before(time2, time1)
+    }
+}
+
+/// Provides joins on streams of `(key, value)` pairs that can be migrated.
+///
+/// Records are matched on equal keys. Each pair of matching records is reported exactly once, at
+/// the time of the later of both records. Unmatched records are reported at the time they are
+/// evicted.
+pub trait Join<S, K, V>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder+ExchangeData,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Eq,
 {
-    fn left_join<V2>(&mut self, other: &Stream<S, (K, V2)>, name: &str, control: &Stream<S, ::Control>) -> Stream<S, (K, V, V2)>
+    /// Inner join: reports `(key, value1, value2)` for each pair of matching records.
+    fn join<V2>(&self, other: &Stream<S, (K, V2)>, retention: Retention<S::Timestamp>, control: &Stream<S, Control>) -> Stream<S, (K, V, V2)>
         where
-            V2: ExchangeData+Eq,
-;
+            V2: ExchangeData+Eq;
+
+    /// Left outer join: like `join`, but additionally reports `(key, value1, None)` for records of
+    /// this stream that did not match any record once they are evicted.
+    ///
+    /// Unmatched records are only reported when they expire, i.e. never under `Retention::Forever`.
+    fn left_join<V2>(&self, other: &Stream<S, (K, V2)>, retention: Retention<S::Timestamp>, control: &Stream<S, Control>) -> Stream<S, (K, V, Option<V2>)>
+        where
+            V2: ExchangeData+Eq;
+
+    /// Full outer join: like `join`, but additionally reports records of both inputs that did not
+    /// match any record once they are evicted, with `None` in place of the missing side.
+    ///
+    /// Unmatched records are only reported when they expire, i.e. never under `Retention::Forever`.
+    fn outer_join<V2>(&self, other: &Stream<S, (K, V2)>, retention: Retention<S::Timestamp>, control: &Stream<S, Control>) -> Stream<S, (K, Option<V>, Option<V2>)>
+        where
+            V2: ExchangeData+Eq;
 }
 
-impl<S, K, V> BinarySkeleton<S, K, V> for Stream<S, (K, V)>
+impl<S, K, V> Join<S, K, V> for Stream<S, (K, V)>
 where
-    S: Scope, // The containing scope
-    S::Timestamp: ::timely::order::TotalOrder,
+    S: Scope,
+    S::Timestamp: TotalOrder+ExchangeData,
     K: ExchangeData+Hash+Eq,
-    V: ExchangeData+Eq, // Input data
+    V: ExchangeData+Eq,
 {
-    fn left_join<V2>(&mut self, other: &Stream<S, (K, V2)>, name: &str, control: &Stream<S, ::Control>) -> Stream<S, (K, V, V2)>
+    fn join<V2>(&self, other: &Stream<S, (K, V2)>, retention: Retention<S::Timestamp>, control: &Stream<S, Control>) -> Stream<S, (K, V, V2)>
+        where
+            V2: ExchangeData+Eq,
+    {
+        join_core(self, other, retention, control, "Join", false, false)
+            .map(|(key, value1, value2)| (key, value1.expect("unmatched left record"), value2.expect("unmatched right record")))
+    }
+
+    fn left_join<V2>(&self, other: &Stream<S, (K, V2)>, retention: Retention<S::Timestamp>, control: &Stream<S, Control>) -> Stream<S, (K, V, Option<V2>)>
         where
             V2: ExchangeData+Eq,
     {
-        self.stateful_binary(&control, other, |t| calculate_hash(&t.0), |t| calculate_hash(&t.0), name, |cap, data, bin1: &mut Bin<_, HashMap<K, V>, _>, bin2: &mut Bin<_, HashMap<K, Vec<V2>>, _>, output| {
-            let mut session = output.session(&cap);
-            let bin: &mut HashMap<_, _> = bin2.state();
-            for (_time, (key, value)) in data {
-                if let Some(mut d2) = bin.remove(&key) {
-                    session.give_iterator(d2.drain(..).map(|d| (key.clone(), value.clone(), d)));
+        join_core(self, other, retention, control, "LeftJoin", true, false)
+            .map(|(key, value1, value2)| (key, value1.expect("unmatched right record"), value2))
+    }
+
+    fn outer_join<V2>(&self, other: &Stream<S, (K, V2)>, retention: Retention<S::Timestamp>, control: &Stream<S, Control>) -> Stream<S, (K, Option<V>, Option<V2>)>
+        where
+            V2: ExchangeData+Eq,
+    {
+        join_core(self, other, retention, control, "OuterJoin", true, true)
+    }
+}
+
+/// Records stored per key: time, value and whether the record matched any other record.
+type JoinState<K, T, V> = HashMap<K, Vec<(T, V, bool)>>;
+
+/// Symmetric hash join of two streams, reporting unmatched records of either side on eviction if
+/// requested.
+///
+/// Notifications carry `(key, Some(value))` for new records and `(key, None)` to request eviction
+/// of the other side's expired records for `key`. Eviction requests for records of one input are
+/// scheduled with the other input's notificator. This way, they are processed in time order with
+/// the records they might still match.
+fn join_core<S, K, V, V2>(
+    stream1: &Stream<S, (K, V)>,
+    stream2: &Stream<S, (K, V2)>,
+    retention: Retention<S::Timestamp>,
+    control: &Stream<S, Control>,
+    name: &str,
+    unmatched1: bool,
+    unmatched2: bool) -> Stream<S, (K, Option<V>, Option<V2>)>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder+ExchangeData,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Eq,
+    V2: ExchangeData+Eq,
+{
+    let retention2 = retention.clone();
+    let mut data1_buffer = vec![];
+    let mut data2_buffer = vec![];
+
+    stream1.stateful_binary_input(control, stream2, |d| calculate_hash(&d.0), |d| calculate_hash(&d.0), name,
+        move |state, cap, time, data, _output| {
+            data.swap(&mut data1_buffer);
+            for (_worker, key_id, (key, value)) in data1_buffer.drain(..) {
+                state.get(key_id).notificator().notify_at_data(&cap, time.clone(), (key, Some(value)));
+            }
+        },
+        move |state, cap, time, data, _output| {
+            data.swap(&mut data2_buffer);
+            for (_worker, key_id, (key, value)) in data2_buffer.drain(..) {
+                state.get(key_id).notificator().notify_at_data(&cap, time.clone(), (key, Some(value)));
+            }
+        },
+        move |cap, data, bin1, bin2, output| {
+            for (time, (key, value)) in data.drain(..) {
+                match value {
+                    Some(value) => {
+                        let mut matched = false;
+                        let state2: &mut JoinState<_, _, _> = bin2.state();
+                        if let Some(records) = state2.get_mut(&key) {
+                            for &mut (ref time2, ref value2, ref mut matched2) in records.iter_mut() {
+                                if retention.matches(&time, time2) {
+                                    matched = true;
+                                    *matched2 = true;
+                                    output.session(&cap.delayed(max(&time, time2))).give((key.clone(), Some(value.clone()), Some(value2.clone())));
+                                }
+                            }
+                        }
+                        if let Some(expiry) = retention.expiry(&time) {
+                            bin2.notificator().notify_at_data(&cap, expiry, (key.clone(), None));
+                        }
+                        let state1: &mut JoinState<_, _, _> = bin1.state();
+                        state1.entry(key).or_insert_with(Vec::new).push((time, value, matched));
+                    },
+                    None => {
+                        let unmatched = evict(bin2.state(), &key, &time, &retention);
+                        if unmatched2 && !unmatched.is_empty() {
+                            output.session(&cap.delayed(&time)).give_iterator(unmatched.into_iter().map(|value2| (key.clone(), None, Some(value2))));
+                        }
+                    },
                 }
-                bin1.state().insert(key.clone(), value.clone());
-            };
-        }, |cap, data, bin1, bin2, output| {
-            let mut session = output.session(&cap);
-            let state1 = bin1.state();
-            let state2 = bin2.state();
-            for (_time, (key, value)) in data {
-                if let Some(d1) = state1.get(&key) {
-                    session.give((key.clone(), d1.clone(), value.clone()));
-                } else {
-                    state2.entry(key.clone()).or_insert_with(Vec::new).push(value.clone());
+            }
+        },
+        move |cap, data, bin1, bin2, output| {
+            for (time, (key, value)) in data.drain(..) {
+                match value {
+                    Some(value) => {
+                        let mut matched = false;
+                        let state1: &mut JoinState<_, _, _> = bin1.state();
+                        if let Some(records) = state1.get_mut(&key) {
+                            for &mut (ref time1, ref value1, ref mut matched1) in records.iter_mut() {
+                                if retention2.matches(time1, &time) {
+                                    matched = true;
+                                    *matched1 = true;
+                                    output.session(&cap.delayed(max(time1, &time))).give((key.clone(), Some(value1.clone()), Some(value.clone())));
+                                }
+                            }
+                        }
+                        if let Some(expiry) = retention2.expiry(&time) {
+                            bin1.notificator().notify_at_data(&cap, expiry, (key.clone(), None));
+                        }
+                        let state2: &mut JoinState<_, _, _> = bin2.state();
+                        state2.entry(key).or_insert_with(Vec::new).push((time, value, matched));
+                    },
+                    None => {
+                        let unmatched = evict(bin1.state(), &key, &time, &retention2);
+                        if unmatched1 && !unmatched.is_empty() {
+                            output.session(&cap.delayed(&time)).give_iterator(unmatched.into_iter().map(|value1| (key.clone(), Some(value1), None)));
+                        }
+                    },
                 }
-            };
+            }
         })
+}
+
+/// Removes the records for `key` that expired at or before `time` and returns the values of the
+/// records that did not match any other record.
+fn evict<K: Hash+Eq, T: Timestamp, V>(state: &mut JoinState<K, T, V>, key: &K, time: &T, retention: &Retention<T>) -> Vec<V> {
+    let mut unmatched = Vec::new();
+    let empty = if let Some(records) = state.get_mut(key) {
+        let mut index = 0;
+        while index < records.len() {
+            if retention.expiry(&records[index].0).map_or(false, |expiry| expiry.less_equal(time)) {
+                let (_time, value, matched) = records.swap_remove(index);
+                if !matched {
+                    unmatched.push(value);
+                }
+            } else {
+                index += 1;
+            }
+        }
+        records.is_empty()
+    } else {
+        false
+    };
+    if empty {
+        state.remove(key);
     }
+    unmatched
 }
@@ -0,0 +1,417 @@
+//! Per-key state on top of bins.
+//!
+//! [`KeyedState`] maps keys to values and serves as the state of a bin, such that it migrates
+//! with the bin. [`TimedState`] additionally assigns each key a timer key that is unique within
+//! the bin. [`StatefulKeyed`] provides an operator whose `fold` receives each record together
+//! with the state of its key, instead of a whole bin, optionally evicting states after a
+//! time-to-live. [`StateContainer`] abstracts over per-bin containers of per-key states, such as
+//! [`KeyedState`], `BTreeMap` and [`DenseState`].
+//!
+//! [`KeyedState`]: struct.KeyedState.html
+//! [`TimedState`]: struct.TimedState.html
+//! [`StateContainer`]: trait.StateContainer.html
+//! [`DenseState`]: struct.DenseState.html
+//! [`StatefulKeyed`]: trait.StatefulKeyed.html
+use std::collections::BTreeMap;
+use std::collections::hash_map::{Entry, Iter, IterMut, IntoIter};
+use std::hash::Hash;
+
+use fnv::FnvHashMap as HashMap;
+
+use timely::{Data, ExchangeData};
+use timely::dataflow::{Stream, Scope};
+use timely::order::TotalOrder;
+
+use notificator::TimerKeys;
+use operator::StatefulOperator;
+use ::{calculate_hash, Control, BIN_SHIFT};
+
+/// State of a bin, organized by key.
+#[derive(Clone, Debug)]
+pub struct KeyedState<K: Hash+Eq, V> {
+    map: HashMap<K, V>,
+}
+
+impl<K: Hash+Eq, V> Default for KeyedState<K, V> {
+    fn default() -> Self {
+        KeyedState { map: Default::default() }
+    }
+}
+
+impl<K: Hash+Eq, V> KeyedState<K, V> {
+    /// The value of `key`, if any.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key)
+    }
+
+    /// A mutable reference to the value of `key`, if any.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.map.get_mut(key)
+    }
+
+    /// The entry of `key` for in-place manipulation.
+    pub fn entry(&mut self, key: K) -> Entry<K, V> {
+        self.map.entry(key)
+    }
+
+    /// Set the value of `key`, returning the previous value.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.map.insert(key, value)
+    }
+
+    /// Remove `key`, returning its value.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.map.remove(key)
+    }
+
+    /// Returns `true` if `key` has a value.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// The number of keys with a value.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if no key has a value.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterate over all keys and values.
+    pub fn iter(&self) -> Iter<K, V> {
+        self.map.iter()
+    }
+
+    /// Iterate over all keys and mutable values.
+    pub fn iter_mut(&mut self) -> IterMut<K, V> {
+        self.map.iter_mut()
+    }
+
+    /// Keep only the keys for which `predicate` returns `true`.
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, predicate: F) {
+        self.map.retain(predicate)
+    }
+}
+
+impl<K: Hash+Eq, V> IntoIterator for KeyedState<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.into_iter()
+    }
+}
+
+impl<K: Hash+Eq, V> Extend<(K, V)> for KeyedState<K, V> {
+    fn extend<I: IntoIterator<Item=(K, V)>>(&mut self, iter: I) {
+        self.map.extend(iter)
+    }
+}
+
+impl<'a, K: Hash+Eq, V> IntoIterator for &'a KeyedState<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter()
+    }
+}
+
+/// State of a bin, organized by key, with a timer key per entry that is unique within the bin.
+///
+/// Timer keys are allocated from the hashes of the keys with `TimerKeys`, such that keys with
+/// colliding hashes do not share timers. They migrate with the states and are registered again on
+/// arrival.
+#[derive(Clone, Debug)]
+pub struct TimedState<K: Hash+Eq, V> {
+    map: HashMap<K, (u64, V)>,
+    timer_keys: TimerKeys,
+}
+
+impl<K: Hash+Eq, V> Default for TimedState<K, V> {
+    fn default() -> Self {
+        TimedState { map: Default::default(), timer_keys: Default::default() }
+    }
+}
+
+impl<K: Hash+Eq, V> TimedState<K, V> {
+    /// The value of `key`, if any.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key).map(|&(_, ref value)| value)
+    }
+
+    /// The timer key of `key` together with a mutable reference to its value, inserting the
+    /// result of `default` if there is none.
+    pub fn get_or_insert_with<F: FnOnce()->V>(&mut self, key: &K, default: F) -> (u64, &mut V) where K: Clone {
+        if !self.map.contains_key(key) {
+            let timer_key = self.timer_keys.allocate(calculate_hash(key));
+            self.map.insert(key.clone(), (timer_key, default()));
+        }
+        let &mut (timer_key, ref mut value) = self.map.get_mut(key).unwrap();
+        (timer_key, value)
+    }
+
+    /// Remove `key`, returning its timer key and value. The timer key may be allocated again, any
+    /// pending timer with it should be cancelled.
+    pub fn remove(&mut self, key: &K) -> Option<(u64, V)> {
+        let removed = self.map.remove(key);
+        if let Some((timer_key, _)) = removed {
+            self.timer_keys.release(timer_key);
+        }
+        removed
+    }
+
+    /// The number of keys with a value.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if no key has a value.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<K: Hash+Eq, V> IntoIterator for TimedState<K, V> {
+    type Item = (K, (u64, V));
+    type IntoIter = IntoIter<K, (u64, V)>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.into_iter()
+    }
+}
+
+impl<K: Hash+Eq, V> Extend<(K, (u64, V))> for TimedState<K, V> {
+    fn extend<I: IntoIterator<Item=(K, (u64, V))>>(&mut self, iter: I) {
+        for (key, (timer_key, value)) in iter {
+            self.timer_keys.insert(timer_key);
+            self.map.insert(key, (timer_key, value));
+        }
+    }
+}
+
+/// A per-bin container of per-key states.
+///
+/// Containers migrate as sequences of `(key, state)` pairs.
+pub trait StateContainer<K, D>: Clone+IntoIterator<Item=(K, D)>+Extend<(K, D)>+Default+'static {
+    /// The state of `key`, inserting `D::default()` if there is none.
+    fn get_or_default(&mut self, key: &K) -> &mut D;
+    /// Remove the state of `key`.
+    fn remove_key(&mut self, key: &K);
+}
+
+impl<K: Hash+Eq+Clone+'static, D: Clone+Default+'static> StateContainer<K, D> for HashMap<K, D> {
+    fn get_or_default(&mut self, key: &K) -> &mut D {
+        if !self.contains_key(key) {
+            self.insert(key.clone(), Default::default());
+        }
+        self.get_mut(key).unwrap()
+    }
+    fn remove_key(&mut self, key: &K) {
+        self.remove(key);
+    }
+}
+
+impl<K: Hash+Eq+Clone+'static, D: Clone+Default+'static> StateContainer<K, D> for KeyedState<K, D> {
+    fn get_or_default(&mut self, key: &K) -> &mut D {
+        self.map.get_or_default(key)
+    }
+    fn remove_key(&mut self, key: &K) {
+        self.map.remove(key);
+    }
+}
+
+impl<K: Ord+Clone+'static, D: Clone+Default+'static> StateContainer<K, D> for BTreeMap<K, D> {
+    fn get_or_default(&mut self, key: &K) -> &mut D {
+        if !self.contains_key(key) {
+            self.insert(key.clone(), Default::default());
+        }
+        self.get_mut(key).unwrap()
+    }
+    fn remove_key(&mut self, key: &K) {
+        self.remove(key);
+    }
+}
+
+/// A dense container for small integer keys.
+///
+/// Intended for keys routed by `key << (64 - BIN_SHIFT)`, which places key `k` in bin
+/// `k % (1 << BIN_SHIFT)`. The state of `k` is stored at position `k >> BIN_SHIFT` of the bin's
+/// vector, avoiding hashing altogether.
+#[derive(Clone, Debug)]
+pub struct DenseState<D> {
+    states: Vec<Option<(usize, D)>>,
+}
+
+impl<D> Default for DenseState<D> {
+    fn default() -> Self {
+        DenseState { states: Vec::new() }
+    }
+}
+
+impl<D: Clone+Default+'static> StateContainer<usize, D> for DenseState<D> {
+    fn get_or_default(&mut self, key: &usize) -> &mut D {
+        let position = key >> BIN_SHIFT;
+        if self.states.len() <= position {
+            let missing = position + 1 - self.states.len();
+            self.states.extend(::std::iter::repeat_with(|| None).take(missing));
+        }
+        let entry = &mut self.states[position];
+        if entry.is_none() {
+            *entry = Some((*key, Default::default()));
+        }
+        &mut entry.as_mut().unwrap().1
+    }
+    fn remove_key(&mut self, key: &usize) {
+        if let Some(entry) = self.states.get_mut(key >> BIN_SHIFT) {
+            entry.take();
+        }
+    }
+}
+
+impl<D> IntoIterator for DenseState<D> {
+    type Item = (usize, D);
+    type IntoIter = ::std::iter::Flatten<::std::vec::IntoIter<Option<(usize, D)>>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.states.into_iter().flatten()
+    }
+}
+
+impl<D: Clone+Default+'static> Extend<(usize, D)> for DenseState<D> {
+    fn extend<I: IntoIterator<Item=(usize, D)>>(&mut self, iter: I) {
+        for (key, state) in iter {
+            *self.get_or_default(&key) = state;
+        }
+    }
+}
+
+/// Notifications of the keyed operator with state expiry.
+#[derive(Abomonation, Clone, Debug, Eq, PartialEq)]
+enum TtlEvent<K, V> {
+    /// A record to apply
+    Record(K, V),
+    /// The state of the key may have expired
+    Expire(K),
+}
+
+/// Provide an operator maintaining migratable per-key state.
+pub trait StatefulKeyed<S, K, V>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Eq,
+{
+    /// Applies `fold` to each record together with the state of its key, in time order.
+    ///
+    /// States are created with `Default` on first use. `fold` receives the record's time, key,
+    /// value and state, and returns whether to remove the key's state together with the output
+    /// records.
+    fn stateful_keyed<
+        D: ExchangeData+Default,                                // per-key state
+        R: Data,                                                // output type
+        I: IntoIterator<Item=R>,                                // type of output iterator
+        F: FnMut(&S::Timestamp, &K, V, &mut D)->(bool, I)+'static, // state update logic
+    >(&self, name: &str, control: &Stream<S, Control>, fold: F) -> Stream<S, R>;
+
+    /// Like `stateful_keyed`, but evicts the state of keys that have not been updated for a while.
+    ///
+    /// `expiry` receives the time and value of each record and returns the time at which the
+    /// key's state expires unless a later record updates it. Expressing this in operator
+    /// timestamps, e.g. `|time, _| time + ttl`, or in event time carried by the records is up to
+    /// the caller; expiry times before the record's time expire the state at the record's time.
+    /// Eviction is driven by the bin notificator once the input frontier passes the expiry time,
+    /// such that evicted states are not migrated with their bin.
+    fn stateful_keyed_ttl<
+        D: ExchangeData+Default,                                // per-key state
+        R: Data,                                                // output type
+        I: IntoIterator<Item=R>,                                // type of output iterator
+        E: Fn(&S::Timestamp, &V)->S::Timestamp+'static,         // expiry time of a record
+        F: FnMut(&S::Timestamp, &K, V, &mut D)->(bool, I)+'static, // state update logic
+    >(&self, name: &str, control: &Stream<S, Control>, expiry: E, fold: F) -> Stream<S, R>;
+}
+
+impl<S, K, V> StatefulKeyed<S, K, V> for Stream<S, (K, V)>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Eq,
+{
+    fn stateful_keyed<
+        D: ExchangeData+Default,
+        R: Data,
+        I: IntoIterator<Item=R>,
+        F: FnMut(&S::Timestamp, &K, V, &mut D)->(bool, I)+'static,
+    >(&self, name: &str, control: &Stream<S, Control>, mut fold: F) -> Stream<S, R> {
+        self.stateful_unary(control, |(key, _value)| calculate_hash(key), name, move |cap, data, bin, output| {
+            let states: &mut KeyedState<K, D> = bin.state();
+            let mut session_cap = cap.clone();
+            for (time, (key, value)) in data.drain(..) {
+                if *session_cap.time() != time {
+                    session_cap = cap.delayed(&time);
+                }
+                let (remove, records) = {
+                    let state = states.entry(key.clone()).or_insert_with(Default::default);
+                    fold(&time, &key, value, state)
+                };
+                if remove {
+                    states.remove(&key);
+                }
+                output.session(&session_cap).give_iterator(records.into_iter());
+            }
+        })
+    }
+
+    fn stateful_keyed_ttl<
+        D: ExchangeData+Default,
+        R: Data,
+        I: IntoIterator<Item=R>,
+        E: Fn(&S::Timestamp, &V)->S::Timestamp+'static,
+        F: FnMut(&S::Timestamp, &K, V, &mut D)->(bool, I)+'static,
+    >(&self, name: &str, control: &Stream<S, Control>, expiry: E, mut fold: F) -> Stream<S, R> {
+        let mut data_buffer = vec![];
+        self.stateful_unary_input(control, |(key, _value)| calculate_hash(key), name, move |state, cap, time, data, _output| {
+            data.swap(&mut data_buffer);
+            for (_worker, key_id, (key, value)) in data_buffer.drain(..) {
+                state.get(key_id).notificator().notify_at_data(cap, time.clone(), TtlEvent::Record(key, value));
+            }
+        }, move |cap, data, bin, output| {
+            // Apply records before expirations at the same time
+            data.sort_by_key(|&(ref time, ref event)| (time.clone(), match *event { TtlEvent::Record(..) => 0, TtlEvent::Expire(_) => 1 }));
+            let mut session_cap = cap.clone();
+            for (time, event) in data.drain(..) {
+                match event {
+                    TtlEvent::Record(key, value) => {
+                        if *session_cap.time() != time {
+                            session_cap = cap.delayed(&time);
+                        }
+                        let expires = ::std::cmp::max(expiry(&time, &value), time.clone());
+                        let (timer_key, remove, records) = {
+                            // States carry their expiry time
+                            let states: &mut TimedState<K, (S::Timestamp, D)> = bin.state();
+                            let (timer_key, entry) = states.get_or_insert_with(&key, || (time.clone(), Default::default()));
+                            entry.0 = expires.clone();
+                            let (remove, records) = fold(&time, &key, value, &mut entry.1);
+                            (timer_key, remove, records)
+                        };
+                        if remove {
+                            let states: &mut TimedState<K, (S::Timestamp, D)> = bin.state();
+                            states.remove(&key);
+                            bin.notificator().cancel(timer_key);
+                        } else {
+                            bin.notificator().notify_at_key(&cap, timer_key, expires, TtlEvent::Expire(key));
+                        }
+                        output.session(&session_cap).give_iterator(records.into_iter());
+                    },
+                    TtlEvent::Expire(key) => {
+                        // A record at the same time may have extended the state's lifetime
+                        let states: &mut TimedState<K, (S::Timestamp, D)> = bin.state();
+                        if states.get(&key).map_or(false, |&(ref expires, _)| *expires <= time) {
+                            states.remove(&key);
+                        }
+                    },
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,173 @@
+//! Per-epoch end-to-end latency measurement.
+//!
+//! A [`LatencyRecorder`] collects the latency of each epoch of a dataflow on the local worker. The
+//! [`MeasureLatency`] operators mark where measurement starts and ends: `latency_start` records the
+//! wall-clock time at which the first record of an epoch arrives, and `latency_end` completes the
+//! epoch once its input frontier has passed the epoch's time. Latencies are available per epoch
+//! and aggregated in an HDR histogram.
+//!
+//! ```
+//! extern crate timely;
+//! extern crate dynamic_scaling_mechanism;
+//!
+//! use timely::Configuration;
+//! use timely::dataflow::{InputHandle, ProbeHandle};
+//! use timely::dataflow::operators::{Input, Map, Probe};
+//! use dynamic_scaling_mechanism::latency::{LatencyRecorder, MeasureLatency};
+//!
+//! fn main() {
+//!     timely::execute(Configuration::Thread, |worker| {
+//!         let recorder = LatencyRecorder::new();
+//!         let mut input = InputHandle::new();
+//!         let mut probe = ProbeHandle::new();
+//!         worker.dataflow(|scope| {
+//!             scope.input_from(&mut input)
+//!                 .latency_start(&recorder)
+//!                 .map(|x: u64| x + 1)
+//!                 .latency_end(&recorder)
+//!                 .probe_with(&mut probe);
+//!         });
+//!         for round in 0..10 {
+//!             input.send(round);
+//!             input.advance_to(round + 1);
+//!             while probe.less_than(input.time()) {
+//!                 worker.step();
+//!             }
+//!         }
+//!         assert_eq!(10, recorder.epochs().len());
+//!     }).unwrap();
+//! }
+//! ```
+//!
+//! [`LatencyRecorder`]: struct.LatencyRecorder.html
+//! [`MeasureLatency`]: trait.MeasureLatency.html
+use std::cell::{Ref, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use hdrhist::HDRHist;
+
+use timely::Data;
+use timely::dataflow::{Stream, Scope};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
+use timely::order::TotalOrder;
+use timely::progress::Timestamp;
+
+/// Measurements shared between the operators of a recorder.
+struct Measurements<T> {
+    /// Epochs that started but did not complete yet
+    started: BTreeMap<T, Instant>,
+    /// Completed epochs and their latency, in order of completion
+    completed: Vec<(T, Duration)>,
+    /// Latencies of all completed epochs in nanoseconds
+    histogram: HDRHist,
+}
+
+/// Collects per-epoch latencies of a dataflow on the local worker.
+///
+/// Clones share the same measurements.
+pub struct LatencyRecorder<T: Timestamp> {
+    measurements: Rc<RefCell<Measurements<T>>>,
+}
+
+impl<T: Timestamp> LatencyRecorder<T> {
+    /// Construct a recorder without measurements.
+    pub fn new() -> Self {
+        let measurements = Measurements {
+            started: BTreeMap::new(),
+            completed: Vec::new(),
+            histogram: HDRHist::new(),
+        };
+        LatencyRecorder { measurements: Rc::new(RefCell::new(measurements)) }
+    }
+
+    /// Record the start of `time`, unless it has already started.
+    fn start(&self, time: &T) {
+        let mut measurements = self.measurements.borrow_mut();
+        if !measurements.started.contains_key(time) {
+            measurements.started.insert(time.clone(), Instant::now());
+        }
+    }
+
+    /// Complete all started epochs for which `complete` returns `true`.
+    fn complete<F: Fn(&T) -> bool>(&self, complete: F) {
+        let now = Instant::now();
+        let mut measurements = self.measurements.borrow_mut();
+        let done = measurements.started.keys().take_while(|time| complete(time)).cloned().collect::<Vec<_>>();
+        for time in done {
+            let start = measurements.started.remove(&time).unwrap();
+            let latency = now.duration_since(start);
+            measurements.histogram.add_value(latency.as_secs() * 1_000_000_000 + latency.subsec_nanos() as u64);
+            measurements.completed.push((time, latency));
+        }
+    }
+
+    /// The latencies of completed epochs, in order of completion.
+    pub fn epochs(&self) -> Vec<(T, Duration)> {
+        self.measurements.borrow().completed.clone()
+    }
+
+    /// The number of epochs that started but did not complete yet.
+    pub fn pending(&self) -> usize {
+        self.measurements.borrow().started.len()
+    }
+
+    /// The histogram of latencies of completed epochs, in nanoseconds.
+    pub fn histogram(&self) -> Ref<HDRHist> {
+        Ref::map(self.measurements.borrow(), |measurements| &measurements.histogram)
+    }
+}
+
+impl<T: Timestamp> Clone for LatencyRecorder<T> {
+    fn clone(&self) -> Self {
+        LatencyRecorder { measurements: Rc::clone(&self.measurements) }
+    }
+}
+
+impl<T: Timestamp> Default for LatencyRecorder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Operators marking the start and end of latency measurement.
+pub trait MeasureLatency<G: Scope, D: Data> where G::Timestamp: TotalOrder {
+    /// Passes data through and starts each epoch when its first record arrives.
+    fn latency_start(&self, recorder: &LatencyRecorder<G::Timestamp>) -> Stream<G, D>;
+
+    /// Passes data through and completes each started epoch once the input frontier passes it.
+    fn latency_end(&self, recorder: &LatencyRecorder<G::Timestamp>) -> Stream<G, D>;
+}
+
+impl<G: Scope, D: Data> MeasureLatency<G, D> for Stream<G, D> where G::Timestamp: TotalOrder {
+    fn latency_start(&self, recorder: &LatencyRecorder<G::Timestamp>) -> Stream<G, D> {
+        let recorder = recorder.clone();
+        let mut data_buffer = Vec::new();
+        self.unary(Pipeline, "LatencyStart", move |_cap, _info| {
+            move |input, output| {
+                input.for_each(|time, data| {
+                    recorder.start(time.time());
+                    data.swap(&mut data_buffer);
+                    output.session(&time).give_vec(&mut data_buffer);
+                });
+            }
+        })
+    }
+
+    fn latency_end(&self, recorder: &LatencyRecorder<G::Timestamp>) -> Stream<G, D> {
+        let recorder = recorder.clone();
+        let mut data_buffer = Vec::new();
+        self.unary_frontier(Pipeline, "LatencyEnd", move |_cap, _info| {
+            move |input, output| {
+                input.for_each(|time, data| {
+                    data.swap(&mut data_buffer);
+                    output.session(&time).give_vec(&mut data_buffer);
+                });
+                let frontier = input.frontier();
+                recorder.complete(|time| !frontier.less_equal(time));
+            }
+        })
+    }
+}
@@ -2,20 +2,39 @@
 //! Megaphone is a library to provide migratable operators for timely dataflow.
 
 extern crate fnv;
+extern crate hdrhist;
 extern crate timely;
 extern crate abomonation;
+extern crate rand;
 #[macro_use] extern crate abomonation_derive;
 
+mod stash;
 mod stateful;
+pub mod aggregate;
+pub mod control;
+pub mod distinct;
 pub mod state_machine;
 pub mod join;
+pub mod keyed;
+pub mod latency;
+pub mod topk;
+pub mod window;
 pub mod notificator;
 pub mod operator;
+pub mod probe;
+pub mod testing;
+
+use std::cell::RefCell;
+use std::hash::Hash;
+use std::path::PathBuf;
+use std::rc::Rc;
 
 use timely::order::{PartialOrder, TotalOrder};
 use timely::progress::frontier::Antichain;
 use timely::progress::Timestamp;
 
+use stash::StashMetrics;
+
 /// A control message consisting of a sequence number, a total count of messages to be expected
 /// and an instruction.
 #[derive(Abomonation, Clone, Debug)]
@@ -74,17 +93,63 @@ impl ::std::ops::Deref for Key {
     }
 }
 
+/// Hash a key with the FNV hasher, used to partition keyed streams.
+fn calculate_hash<T: Hash>(t: &T) -> u64 {
+    use ::std::hash::Hasher;
+    let mut h: ::fnv::FnvHasher = Default::default();
+    t.hash(&mut h);
+    h.finish()
+}
+
 /// A control instruction
-#[derive(Abomonation, Clone, Debug)]
+#[derive(Abomonation, Clone, Debug, Eq, PartialEq)]
 pub enum ControlInst {
     /// Provide a new map
     Map(Vec<usize>),
     /// Provide a map update
     Move(BinId, /*worker*/ usize),
+    /// Withdraw the pending configuration with the given sequence number if its time is after the
+    /// time of this instruction. Other instructions in the same batch form a configuration that
+    /// replaces it.
+    Abort(/*sequence*/ u64),
     /// No-op
     None,
 }
 
+impl ControlInst {
+    /// Check that the instruction only refers to existing bins and to workers in `0..peers`.
+    ///
+    /// #Examples
+    /// ```
+    /// use dynamic_scaling_mechanism::{BinId, ControlInst};
+    ///
+    /// assert!(ControlInst::Move(BinId::new(0), 1).validate(2).is_ok());
+    /// assert!(ControlInst::Move(BinId::new(0), 2).validate(2).is_err());
+    /// ```
+    pub fn validate(&self, peers: usize) -> Result<(), String> {
+        match *self {
+            ControlInst::Map(ref map) => {
+                if map.len() != 1 << BIN_SHIFT {
+                    return Err(format!("provided map does not have correct len: {} != {}", 1 << BIN_SHIFT, map.len()));
+                }
+                if let Some(worker) = map.iter().find(|&&worker| worker >= peers) {
+                    return Err(format!("map refers to worker {}, but there are only {} workers", worker, peers));
+                }
+            },
+            ControlInst::Move(BinId(bin), worker) => {
+                if bin >= 1 << BIN_SHIFT {
+                    return Err(format!("bin {} out of range, there are only {} bins", bin, 1 << BIN_SHIFT));
+                }
+                if worker >= peers {
+                    return Err(format!("move of bin {} refers to worker {}, but there are only {} workers", bin, worker, peers));
+                }
+            },
+            ControlInst::Abort(_) | ControlInst::None => {},
+        }
+        Ok(())
+    }
+}
+
 impl Control {
     /// Construct a new `Control`
     pub fn new(sequence: u64, count: usize, inst: ControlInst) -> Self {
@@ -101,15 +166,46 @@ pub struct ControlSet<T> {
     pub frontier: Antichain<T>,
     /// Explicit mapping of bins to workers
     pub map: Vec<usize>,
+    /// The instructions that derived `map` from the preceding configuration
+    instructions: Vec<ControlInst>,
 }
 
 impl<T> ControlSet<T> {
 
+    /// Construct a `ControlSet` from an explicit map.
+    pub fn new(sequence: u64, frontier: Antichain<T>, map: Vec<usize>) -> Self {
+        ControlSet { sequence, frontier, map, instructions: Vec::new() }
+    }
+
     /// Obtain the current bin to destination mapping
     pub fn map(&self) -> &Vec<usize> {
         &self.map
     }
 
+    /// Recompute the map by applying this configuration's instructions to `previous`. Required
+    /// when a configuration this one was built on has been withdrawn.
+    pub fn rebase(&mut self, previous: &ControlSet<T>) {
+        self.map = apply_instructions(previous.map(), &self.instructions);
+    }
+
+}
+
+/// Apply `instructions` to a copy of `map`.
+fn apply_instructions(map: &[usize], instructions: &[ControlInst]) -> Vec<usize> {
+    let mut map = map.to_vec();
+    for inst in instructions {
+        match *inst {
+            ControlInst::Map(ref new_map) => {
+                map.clear();
+                map.extend( new_map.iter());
+            },
+            ControlInst::Move(BinId(bin), target) => {
+                map[bin] = target
+            },
+            ControlInst::Abort(_) | ControlInst::None => {},
+        }
+    }
+    map
 }
 
 /// A builder to compile `ControlSet`s.
@@ -118,31 +214,44 @@ pub struct ControlSetBuilder<T> {
     sequence: Option<u64>,
     frontier: Vec<T>,
     instructions: Vec<ControlInst>,
+    withdrawn: Vec<u64>,
 
     count: Option<usize>,
+    error: Option<String>,
 }
 
 impl<T: PartialOrder> ControlSetBuilder<T> {
 
     /// Add a new `Control` to this builder.
     ///
-    /// TODO: Currently, it will assert if count and sequence numbers do not match. Should fail
-    /// gracefully instead.
+    /// A `Control` whose count or sequence number does not match the preceding ones invalidates
+    /// the batch, which `validate` then reports.
     pub fn apply(&mut self, control: Control) {
-        if self.count.is_none() {
-            self.count = Some(control.count);
+        if self.error.is_some() {
+            return;
         }
-        if let Some(ref mut count) = self.count {
-            assert!(*count > 0, "Received incorrect number of Controls");
-            *count -= 1;
+        match self.count {
+            None if control.count == 0 => {
+                self.error = Some("received a control with a count of zero".to_string());
+                return;
+            },
+            None => self.count = Some(control.count - 1),
+            Some(0) => {
+                self.error = Some(format!("received more than {} controls", control.count));
+                return;
+            },
+            Some(ref mut count) => *count -= 1,
         }
-        if let Some(sequence) = self.sequence {
-            assert_eq!(sequence, control.sequence, "Received control with inconsistent sequence number");
-        } else {
-            self.sequence = Some(control.sequence);
+        match self.sequence {
+            Some(sequence) if sequence != control.sequence => {
+                self.error = Some(format!("inconsistent sequence numbers {} and {}", sequence, control.sequence));
+                return;
+            },
+            _ => self.sequence = Some(control.sequence),
         }
         match control.inst {
             ControlInst::None => {},
+            ControlInst::Abort(sequence) => self.withdrawn.push(sequence),
             inst => self.instructions.push(inst),
         };
 
@@ -153,37 +262,204 @@ impl<T: PartialOrder> ControlSetBuilder<T> {
         self.frontier.extend(caps);
     }
 
+    /// The sequence numbers of pending configurations this batch withdraws.
+    pub fn withdrawn(&self) -> &[u64] {
+        &self.withdrawn
+    }
+
+    /// Returns `true` if the batch only withdraws configurations and does not provide a new one.
+    pub fn is_withdrawal(&self) -> bool {
+        !self.withdrawn.is_empty() && self.instructions.is_empty()
+    }
+
+    /// Check that the batch is complete and consistent, and that all instructions refer to
+    /// existing bins and to workers in `0..peers`.
+    pub fn validate(&self, peers: usize) -> Result<(), String> {
+        if let Some(ref error) = self.error {
+            return Err(error.clone());
+        }
+        match self.count {
+            Some(0) => {},
+            Some(missing) => return Err(format!("missing {} controls", missing)),
+            None => return Err("empty batch".to_string()),
+        }
+        for inst in &self.instructions {
+            inst.validate(peers).map_err(|e| format!("invalid control instruction {:?}: {}", inst, e))?;
+        }
+        Ok(())
+    }
+
     /// Build a `ControlSet` by consuming this builder.
-    pub fn build(self, previous: &ControlSet<T>) -> ControlSet<T> {
-        assert_eq!(0, self.count.unwrap_or(0));
+    ///
+    /// Returns an error if the batch is incomplete or inconsistent, or if an instruction refers to a
+    /// worker outside of `0..peers` or to a non-existing bin.
+    pub fn build(self, previous: &ControlSet<T>, peers: usize) -> Result<ControlSet<T>, String> {
+        self.validate(peers)?;
         let mut frontier = Antichain::new();
         for f in self.frontier {frontier.insert(f);}
 
-        let mut map = previous.map().clone();
-
-        for inst in self.instructions {
-            match inst {
-                ControlInst::Map(ref new_map) => {
-                    assert_eq!(1 << BIN_SHIFT, new_map.len(), "provided map does not have correct len: {} != {}", 1 << BIN_SHIFT, new_map.len());
-                    map.clear();
-                    map.extend( new_map.iter());
-                },
-                ControlInst::Move(BinId(bin), target) => {
-                    assert!(bin < (1 << BIN_SHIFT));
-                    map[bin] = target
-                },
-                ControlInst::None => {},
-            }
-        }
+        let map = apply_instructions(previous.map(), &self.instructions);
 
-        ControlSet {
+        Ok(ControlSet {
             sequence: self.sequence.unwrap(),
             frontier,
             map,
+            instructions: self.instructions,
+        })
+    }
+}
+
+/// Determines how a stateful operator routes its input to workers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StatefulMode {
+    /// Route records according to the bin-to-worker map and migrate bins as instructed by the
+    /// control stream.
+    Megaphone,
+    /// Route records by a plain exchange on their key and ignore the control stream. Each worker
+    /// holds all bins and no state is migrated. Serves as a non-migrating baseline.
+    Exchange,
+}
+
+impl Default for StatefulMode {
+    /// `Exchange` if the "fake_stateful" feature is enabled, `Megaphone` otherwise.
+    fn default() -> Self {
+        if cfg!(feature = "fake_stateful") {
+            StatefulMode::Exchange
+        } else {
+            StatefulMode::Megaphone
         }
     }
 }
 
+/// The assignment of bins to workers before the first configuration is installed.
+#[derive(Clone)]
+enum InitialMap {
+    /// Assign bin `i` to worker `i % peers`
+    RoundRobin,
+    /// An explicit map
+    Map(Vec<usize>),
+    /// A function of bin and number of peers
+    Placement(Rc<Fn(BinId, usize) -> usize>),
+}
+
+impl Default for InitialMap {
+    fn default() -> Self {
+        InitialMap::RoundRobin
+    }
+}
+
+impl ::std::fmt::Debug for InitialMap {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            InitialMap::RoundRobin => write!(f, "RoundRobin"),
+            InitialMap::Map(ref map) => write!(f, "Map({:?})", map),
+            InitialMap::Placement(_) => write!(f, "Placement(..)"),
+        }
+    }
+}
+
+/// Configuration of a stateful operator.
+#[derive(Clone, Debug, Default)]
+pub struct StatefulConfig {
+    mode: StatefulMode,
+    initial_map: InitialMap,
+    stash_limit: Option<usize>,
+    spill_directory: Option<PathBuf>,
+    stash_metrics: Rc<StashMetrics>,
+    rejected: Rc<RefCell<Vec<String>>>,
+}
+
+impl StatefulConfig {
+    /// Construct a default configuration.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Select how the operator routes its input.
+    pub fn with_mode(mut self, mode: StatefulMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// The configured routing mode.
+    pub fn mode(&self) -> StatefulMode {
+        self.mode
+    }
+
+    /// Assign bins to workers according to `map` from the start, without a migration. The map must
+    /// list all bins.
+    pub fn with_initial_map(mut self, map: Vec<usize>) -> Self {
+        assert_eq!(1 << BIN_SHIFT, map.len(), "provided map does not have correct len: {} != {}", 1 << BIN_SHIFT, map.len());
+        self.initial_map = InitialMap::Map(map);
+        self
+    }
+
+    /// Assign bins to workers by calling `placement` with each bin and the number of peers. The
+    /// result must be a worker index smaller than the number of peers.
+    pub fn with_placement<F: Fn(BinId, usize) -> usize + 'static>(mut self, placement: F) -> Self {
+        self.initial_map = InitialMap::Placement(Rc::new(placement));
+        self
+    }
+
+    /// The initial assignment of bins to `peers` workers. Defaults to assigning bin `i` to worker
+    /// `i % peers`.
+    ///
+    /// Panics if the assignment refers to a worker outside of `0..peers`.
+    pub fn initial_map(&self, peers: usize) -> Vec<usize> {
+        let map = match self.initial_map {
+            InitialMap::RoundRobin => (0..peers).cycle().take(1 << BIN_SHIFT).collect(),
+            InitialMap::Map(ref map) => map.clone(),
+            InitialMap::Placement(ref placement) => (0..1 << BIN_SHIFT).map(|bin| placement(BinId(bin), peers)).collect(),
+        };
+        if let Err(e) = ControlInst::Map(map.clone()).validate(peers) {
+            panic!("Invalid initial map: {}", e);
+        }
+        map
+    }
+
+    /// Limit the bytes each operator keeps in memory for data waiting on the control input.
+    /// Data beyond the limit is spilled to disk. Unlimited by default.
+    pub fn with_stash_limit(mut self, bytes: usize) -> Self {
+        self.stash_limit = Some(bytes);
+        self
+    }
+
+    /// The configured stash limit in bytes, if any.
+    pub fn stash_limit(&self) -> Option<usize> {
+        self.stash_limit
+    }
+
+    /// Select the directory to spill stashed data to. Defaults to the system's temporary directory.
+    pub fn with_spill_directory<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.spill_directory = Some(directory.into());
+        self
+    }
+
+    /// The directory stashed data is spilled to.
+    pub fn spill_directory(&self) -> PathBuf {
+        self.spill_directory.clone().unwrap_or_else(::std::env::temp_dir)
+    }
+
+    /// The number of bytes of stashed data currently held in memory by operators constructed with
+    /// this configuration or its clones.
+    pub fn stashed_bytes(&self) -> usize {
+        self.stash_metrics.in_memory.get()
+    }
+
+    /// The number of bytes of stashed data currently spilled to disk by operators constructed with
+    /// this configuration or its clones.
+    pub fn spilled_bytes(&self) -> usize {
+        self.stash_metrics.spilled.get()
+    }
+
+    /// The control batches operators constructed with this configuration or its clones rejected
+    /// on this worker, described by their time and the reason. Batches issued through the
+    /// [`control`](control/index.html) module are validated before they reach the operators.
+    pub fn rejected_batches(&self) -> Vec<String> {
+        self.rejected.borrow().clone()
+    }
+}
+
 /// State abstraction. It encapsulates state assorted by bins and a notificator.
 pub struct State<T, D, N>
     where
@@ -2,6 +2,8 @@
 
 use std::collections::BinaryHeap;
 
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet};
+
 use timely::order::TotalOrder;
 use timely::progress::frontier::MutableAntichain;
 use timely::progress::Timestamp;
@@ -18,6 +20,17 @@ pub trait Notify<T: Timestamp, D> {
     fn drain(&mut self, frontiers: &[&MutableAntichain<T>], buffer: &mut Vec<(T, D)>) -> Option<Capability<T>>;
 }
 
+/// Notificators supporting keyed notifications (timers), which can be replaced or cancelled as
+/// long as they are pending.
+pub trait Timers<T: Timestamp, D>: Notify<T, D> {
+    /// Request a notification at `time` carrying `data` for the timer identified by `key`. A
+    /// pending timer with the same key is replaced.
+    fn notify_at_key(&mut self, cap: &Capability<T>, key: u64, time: T, data: D);
+
+    /// Cancel the pending timer identified by `key`. Returns `true` if a timer was pending.
+    fn cancel(&mut self, key: u64) -> bool;
+}
+
 /// Tracks requests for notification and delivers available notifications.
 ///
 /// `TotalOrderFrontierNotificator` is meant to manage the delivery of requested notifications in the
@@ -90,6 +103,11 @@ pub trait Notify<T: Timestamp, D> {
 pub struct TotalOrderFrontierNotificator<T: Timestamp + TotalOrder, D = ()> {
     capability: Option<Capability<T>>,
     pending: BinaryHeap<OrderReversed<T, D>>,
+    // Live keyed timers, maps a timer's key to the generation of its pending notification.
+    timers: HashMap<u64, usize>,
+    generation: usize,
+    // Number of superseded or cancelled timers in `pending`
+    stale: usize,
 }
 
 impl<T: Timestamp + TotalOrder> TotalOrderFrontierNotificator<T, ()> {
@@ -100,7 +118,10 @@ impl<T: Timestamp + TotalOrder> TotalOrderFrontierNotificator<T, ()> {
         let capability = pending.iter().min_by_key(|x| x.time()).cloned();
         Self {
             capability,
-            pending: pending.into_iter().map(|x| OrderReversed{ element: x.time().clone(), data: ()}).collect(),
+            pending: pending.into_iter().map(|x| OrderReversed{ element: x.time().clone(), key: None, data: ()}).collect(),
+            timers: Default::default(),
+            generation: 0,
+            stale: 0,
         }
     }
 
@@ -166,6 +187,9 @@ impl<T: Timestamp + TotalOrder, D> TotalOrderFrontierNotificator<T, D> {
             capability: None,
             pending: Default::default(),
 //            available: ::std::collections::BinaryHeap::new(),
+            timers: Default::default(),
+            generation: 0,
+            stale: 0,
         }
     }
 
@@ -206,13 +230,116 @@ impl<T: Timestamp + TotalOrder, D> TotalOrderFrontierNotificator<T, D> {
     /// ```
     #[inline]
     pub fn notify_at_data(&mut self, cap: &Capability<T>, time: T, data: D) {
+        self.push(cap, time, None, data);
+    }
+
+    /// Schedules a timer identified by `key` at `time`, carrying `data`. A pending timer with the
+    /// same `key` is replaced, i.e. only the most recently scheduled notification for a key will
+    /// be delivered.
+    ///
+    /// Timers are migrated with their bin, retaining their key.
+    ///
+    /// #Examples
+    /// ```
+    /// extern crate timely;
+    /// extern crate dynamic_scaling_mechanism;
+    /// use timely::dataflow::operators::ToStream;
+    /// use timely::dataflow::operators::generic::operator::Operator;
+    /// use timely::dataflow::channels::pact::Pipeline;
+    /// use dynamic_scaling_mechanism::notificator::TotalOrderFrontierNotificator;
+    ///
+    /// timely::example(|scope| {
+    ///     (0..10u64).to_stream(scope)
+    ///            .unary_frontier(Pipeline, "example", |_, _| {
+    ///                let mut notificator = TotalOrderFrontierNotificator::new();
+    ///                let mut buffer = Vec::new();
+    ///                move |input, output| {
+    ///                    input.for_each(|cap, data| {
+    ///                        data.swap(&mut buffer);
+    ///                        for x in buffer.drain(..) {
+    ///                            // Extend the deadline for `x % 2`, superseding earlier timers.
+    ///                            let time = *cap.time() + 5;
+    ///                            notificator.notify_at_key(&cap.retain(), x % 2, time, x);
+    ///                        }
+    ///                    });
+    ///                    notificator.for_each_data(&[input.frontier()], |cap, time, data, _| {
+    ///                        output.session(cap).give((time, data));
+    ///                    });
+    ///                }
+    ///            });
+    /// });
+    /// ```
+    #[inline]
+    pub fn notify_at_key(&mut self, cap: &Capability<T>, key: u64, time: T, data: D) {
+        self.generation += 1;
+        if self.timers.insert(key, self.generation).is_some() {
+            self.stale += 1;
+        }
+        let generation = self.generation;
+        self.push(cap, time, Some((key, generation)), data);
+        self.compact();
+    }
+
+    /// Cancels the pending timer identified by `key`. Returns `true` if there was a pending timer
+    /// for `key`.
+    ///
+    /// The capability held by the notificator is downgraded to the earliest remaining
+    /// notification, or released if none remain.
+    #[inline]
+    pub fn cancel(&mut self, key: u64) -> bool {
+        if self.timers.remove(&key).is_none() {
+            return false;
+        }
+        self.stale += 1;
+        self.purge();
+        self.compact();
+        self.downgrade();
+        true
+    }
+
+    #[inline]
+    fn push(&mut self, cap: &Capability<T>, time: T, key: Option<(u64, usize)>, data: D) {
         assert!(cap.time().less_equal(&time), "provided capability must be <= notification time, found {:?} and {:?}", cap.time(), time);
-        self.pending.push(OrderReversed { element: time, data});
+        self.pending.push(OrderReversed { element: time, key, data});
         if self.capability.as_ref().map_or(true, |c| c.time() > cap.time()) {
             self.capability = Some(cap.clone())
         }
     }
 
+    /// Discards superseded or cancelled timers from the head of `pending`.
+    #[inline]
+    fn purge(&mut self) {
+        while self.pending.peek().map_or(false, |or| !or.is_live(&self.timers)) {
+            self.pending.pop();
+            self.stale -= 1;
+        }
+    }
+
+    /// Discards all superseded or cancelled timers once they outnumber the live notifications.
+    #[inline]
+    fn compact(&mut self) {
+        if self.stale > self.pending.len() - self.stale {
+            let pending = ::std::mem::replace(&mut self.pending, BinaryHeap::new());
+            let timers = &self.timers;
+            self.pending = pending.into_iter().filter(|or| or.is_live(timers)).collect();
+            self.stale = 0;
+        }
+    }
+
+    /// Downgrades the capability to the earliest pending notification, or releases it if there
+    /// is none. Expects the head of `pending` to be live.
+    #[inline]
+    fn downgrade(&mut self) {
+        match self.pending.peek() {
+            Some(pending) => if let Some(cap) = self.capability.as_mut() {
+                if cap.time().less_than(&pending.element) {
+                    cap.downgrade(&pending.element);
+                }
+            },
+            None => { self.capability.take(); },
+        }
+    }
+
     /// Repeatedly calls `logic` till exhaustion of the notifications made available by inspecting
     /// the frontiers.
     ///
@@ -227,9 +354,20 @@ impl<T: Timestamp + TotalOrder, D> TotalOrderFrontierNotificator<T, D> {
         }
     }
 
-    /// Descructures the notificator to obtain pending `(time, data)` pairs.
-    pub fn pending(self) -> impl Iterator<Item=(T, D)> {
-        self.pending.into_iter().map(|e| (e.element, e.data))
+    /// The capability held by this notificator, if any. Its time is a lower bound for the times of
+    /// all pending notifications.
+    pub fn capability(&self) -> Option<&Capability<T>> {
+        self.capability.as_ref()
+    }
+
+    /// Descructures the notificator to obtain pending `(time, key, data)` triples. The key is
+    /// present for timers scheduled with `notify_at_key`. Superseded and cancelled timers are
+    /// omitted.
+    pub fn pending(self) -> impl Iterator<Item=(T, Option<u64>, D)> {
+        let timers = self.timers;
+        self.pending.into_iter()
+            .filter(move |e| e.is_live(&timers))
+            .map(|e| (e.element, e.key.map(|(key, _generation)| key), e.data))
     }
 }
 
@@ -242,27 +380,27 @@ impl<T: Timestamp + TotalOrder, D> Notify<T, D> for TotalOrderFrontierNotificato
         // in that the sequence of capabilities in self.available will remain non-decreasing.
 
         let mut result = None;
+        self.purge();
         if !self.pending.is_empty() {
             buffer.clear();
             while self.pending.peek().map_or(false, |or| frontiers.iter().all(|f| !f.less_equal(&or.element))) {
                 let min = self.pending.pop().unwrap();
+                if let Some((key, generation)) = min.key {
+                    // Skip superseded and cancelled timers
+                    if self.timers.get(&key) != Some(&generation) {
+                        self.stale -= 1;
+                        continue;
+                    }
+                    self.timers.remove(&key);
+                }
                 buffer.push((min.element, min.data));
             }
+            self.purge();
             if !buffer.is_empty() {
                 result = Some(self.capability.as_ref().unwrap().clone());
             }
-
-            if let Some(cap) = self.capability.as_mut() {
-                if let Some(pending) = self.pending.peek() {
-                    if cap.time().less_than(&pending.element) {
-                        cap.downgrade(&pending.element);
-                    }
-                }
-            }
-        }
-        if self.pending.is_empty() {
-            self.capability.take();
         }
+        self.downgrade();
 
         if frontiers.iter().all(|f| f.is_empty()) {
             self.capability.take();
@@ -271,11 +409,78 @@ impl<T: Timestamp + TotalOrder, D> Notify<T, D> for TotalOrderFrontierNotificato
     }
 }
 
+impl<T: Timestamp + TotalOrder, D> Timers<T, D> for TotalOrderFrontierNotificator<T, D> {
+
+    #[inline]
+    fn notify_at_key(&mut self, cap: &Capability<T>, key: u64, time: T, data: D) {
+        TotalOrderFrontierNotificator::notify_at_key(self, cap, key, time, data)
+    }
+
+    #[inline]
+    fn cancel(&mut self, key: u64) -> bool {
+        TotalOrderFrontierNotificator::cancel(self, key)
+    }
+}
+
+/// Allocates timer keys for `notify_at_key` that are unique among the live entries of a bin.
+///
+/// A key starts at a hash of its entry and is probed linearly on collision, such that entries
+/// whose hashes collide never replace or cancel each other's timers. Allocated keys should be
+/// stored with the entries' state, such that they migrate with the bin and can be registered
+/// again with `insert` on arrival.
+///
+/// #Examples
+/// ```
+/// use dynamic_scaling_mechanism::notificator::TimerKeys;
+///
+/// let mut keys = TimerKeys::default();
+/// assert_eq!(7, keys.allocate(7));
+/// assert_eq!(8, keys.allocate(7));
+/// keys.release(7);
+/// assert_eq!(7, keys.allocate(7));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TimerKeys {
+    keys: HashSet<u64>,
+}
+
+impl TimerKeys {
+    /// Allocate an unused key, starting at `hash`.
+    pub fn allocate(&mut self, hash: u64) -> u64 {
+        let mut key = hash;
+        while !self.keys.insert(key) {
+            key = key.wrapping_add(1);
+        }
+        key
+    }
+
+    /// Register a key allocated elsewhere, for example before migration.
+    pub fn insert(&mut self, key: u64) {
+        let fresh = self.keys.insert(key);
+        debug_assert!(fresh, "timer key {} registered twice", key);
+    }
+
+    /// Release `key` such that it can be allocated again.
+    pub fn release(&mut self, key: u64) {
+        self.keys.remove(&key);
+    }
+}
+
 struct OrderReversed<T, D> {
     pub element: T,
+    pub key: Option<(u64, usize)>,
     pub data: D,
 }
 
+impl<T, D> OrderReversed<T, D> {
+    /// Plain notifications are always live, timers only if they have not been superseded or
+    /// cancelled.
+    #[inline]
+    fn is_live(&self, timers: &HashMap<u64, usize>) -> bool {
+        self.key.map_or(true, |(key, generation)| timers.get(&key) == Some(&generation))
+    }
+}
+
 impl<T: PartialOrd, D> PartialOrd for OrderReversed<T, D> {
     fn partial_cmp(&self, other: &Self) -> Option<::std::cmp::Ordering> {
         other.element.partial_cmp(&self.element)
@@ -1,22 +1,29 @@
 //! General purpose migratable operators.
 
+use std::path::PathBuf;
+use std::rc::Rc;
+
 use timely::ExchangeData;
 use timely::dataflow::{Stream, Scope};
 use timely::communication::message::RefOrMut;
 use timely::dataflow::channels::pact::Exchange;
 use timely::dataflow::channels::pushers::Tee;
-use timely::dataflow::operators::{ConnectLoop, Filter, Map};
+use timely::dataflow::operators::{ConnectLoop, Filter, Map, Partition};
 use timely::dataflow::operators::generic::builder_rc::OperatorBuilder;
 use timely::Data;
 use timely::dataflow::operators::Capability;
 use timely::dataflow::operators::generic::OutputHandle;
 use timely::order::TotalOrder;
+use timely::progress::frontier::MutableAntichain;
 
-use ::{Bin, Control, Key, State};
+use ::{Bin, BinId, Control, Key, State, StatefulConfig, StatefulMode};
 use stateful::{Stateful, apply_state_updates, Notificator};
 use notificator::{Notify};
 
-/// Building blocks for single- and dual-input stateful operators.
+/// Building blocks for single-, dual- and multi-input stateful operators.
+///
+/// The methods build operators with the default `StatefulConfig`. Use `StatefulBuilder` to
+/// configure an operator, for example to select its routing mode.
 pub trait StatefulOperator<G, D1>
     where
         G: Scope,
@@ -113,6 +120,75 @@ pub trait StatefulOperator<G, D1>
     >(&self, control: &Stream<G, Control>, other: &Stream<G, D2>, key1: B1, key2: B2, name: &str, input1: C1, input2: C2, fold1: F1, fold2: F2) -> Stream<G, D3>
     ;
 
+    /// Stateful operator with a variable number of inputs of the same type.
+    ///
+    /// `self` is the first input and `others` provide the remaining inputs. All inputs share the
+    /// record type `D1` and the state type `S`. Inputs of different types must be wrapped in a
+    /// common enum, both for their records and their state; `stateful_binary` keeps the types of
+    /// two inputs apart instead. All inputs are partitioned according to the same control stream,
+    /// i.e. the bins of all inputs are co-located. Each input maintains its own state and
+    /// notificator per bin.
+    ///
+    /// The key extraction function `key` receives the index of the input and a record. For each bin,
+    /// `fold` receives the index of the input with pending records, the records, and the bins of all
+    /// inputs, ordered by input index.
+    fn stateful_nary<
+        D2: Data,                                    // output type
+        B: Fn(usize, &D1)->u64+'static,              // Key extraction function, receives the input index
+        S: Clone+IntoIterator<Item=W>+Extend<W>+Default+'static, // State type, per input
+        W: ExchangeData,                            // State format on the wire
+        F: FnMut(&Capability<G::Timestamp>,
+            usize,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut [&mut Bin<G::Timestamp, S, D1>],
+            &mut OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>) + 'static,    // state update logic
+    >(&self, control: &Stream<G, Control>, others: &[Stream<G, D1>], key: B, name: &str, fold: F) -> Stream<G, D2>
+    ;
+
+    /// Stateful operator with a single input and `outputs` outputs.
+    ///
+    /// Like `stateful_unary`, but `fold` produces pairs of an output index and a record, and each
+    /// record is delivered to the output with its index, which must be less than `outputs`. Outputs
+    /// of different types can be expressed with an enum. For example, an operator can report late
+    /// records or metrics next to its results.
+    fn stateful_unary_outputs<
+        D2: Data,                                    // output type
+        B: Fn(&D1)->u64+'static,
+        S: Clone+IntoIterator<Item=W>+Extend<W>+Default+'static,
+        W: ExchangeData,                            // State format on the wire
+        F: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut Bin<G::Timestamp, S, D1>,
+            &mut OutputHandle<G::Timestamp, (usize, D2), Tee<G::Timestamp, (usize, D2)>>) + 'static,    // state update logic
+    >(&self, control: &Stream<G, Control>, key: B, name: &str, outputs: usize, fold: F) -> Vec<Stream<G, D2>>
+    ;
+
+    /// Stateful operator with two inputs and `outputs` outputs.
+    ///
+    /// Like `stateful_binary`, but `fold1` and `fold2` produce pairs of an output index and a
+    /// record, as in `stateful_unary_outputs`.
+    fn stateful_binary_outputs<
+        D2: ExchangeData+Eq,                         // input type
+        D3: Data,                                    // output type
+        B1: Fn(&D1)->u64+'static,                    // Key extraction function, input 1
+        B2: Fn(&D2)->u64+'static,                    // Key extraction function, input 2
+        S1: Clone+IntoIterator<Item=W1>+Extend<W1>+Default+'static, // State type, input 1
+        S2: Clone+IntoIterator<Item=W2>+Extend<W2>+Default+'static, // State type, input 2
+        W1: ExchangeData,                            // State format on the wire, input 1
+        W2: ExchangeData,                            // State format on the wire, input 2
+        F1: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut Bin<G::Timestamp, S1, D1>,
+            &mut Bin<G::Timestamp, S2, D2>,
+            &mut OutputHandle<G::Timestamp, (usize, D3), Tee<G::Timestamp, (usize, D3)>>) + 'static,    // state update logic, input 1
+        F2: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D2)>,
+            &mut Bin<G::Timestamp, S1, D1>,
+            &mut Bin<G::Timestamp, S2, D2>,
+            &mut OutputHandle<G::Timestamp, (usize, D3), Tee<G::Timestamp, (usize, D3)>>) + 'static,    // state update logic, input 2
+    >(&self, control: &Stream<G, Control>, other: &Stream<G, D2>, key1: B1, key2: B2, name: &str, outputs: usize, fold1: F1, fold2: F2) -> Vec<Stream<G, D3>>
+    ;
+
     /// Move state to a worker as specified in the control input. Do not maintain state.
     fn distribute<B1>(&self, control: &Stream<G, Control>, key: B1, name: &str) -> Stream<G, (usize, Key, D1)>
     where
@@ -135,12 +211,237 @@ impl<G, D1> StatefulOperator<G, D1> for Stream<G, D1>
             &mut Vec<(G::Timestamp, D1)>,
             &mut Bin<G::Timestamp, S, D1>,
             &mut OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>) + 'static,    // state update logic
-    >(&self, control: &Stream<G, Control>, key: B, name: &str, mut fold: F) -> Stream<G, D2>
+    >(&self, control: &Stream<G, Control>, key: B, name: &str, fold: F) -> Stream<G, D2>
+    {
+        StatefulBuilder::new(name, control).unary(self, key, fold)
+    }
+
+    fn stateful_unary_input<
+        D2: Data,                                    // output type
+        N: ExchangeData+Eq,
+        B: Fn(&D1)->u64+'static,
+        S: Clone+IntoIterator<Item=W>+Extend<W>+Default+'static,
+        W: ExchangeData,                            // State format on the wire
+        F: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, N)>,
+            &mut Bin<G::Timestamp, S, N>,
+            &mut OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>) + 'static,    // state update logic
+        C: FnMut(&mut State<G::Timestamp, S, N>,
+            &Capability<G::Timestamp>,
+            G::Timestamp,
+            RefOrMut<Vec<(usize, Key, D1)>>,
+            &mut OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>) + 'static,
+    >(&self, control: &Stream<G, Control>, key: B, name: &str, consume: C, fold: F) -> Stream<G, D2>
+    {
+        StatefulBuilder::new(name, control).unary_input(self, key, consume, fold)
+    }
+
+    fn stateful_binary<
+        D2: ExchangeData+Eq,                         // input type
+        D3: Data,                                    // output type
+        B1: Fn(&D1)->u64+'static,
+        B2: Fn(&D2)->u64+'static,
+        S1: Clone+IntoIterator<Item=W1>+Extend<W1>+Default+'static,
+        S2: Clone+IntoIterator<Item=W2>+Extend<W2>+Default+'static,
+        W1: ExchangeData,                            // State format on the wire
+        W2: ExchangeData,                            // State format on the wire
+        F1: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut Bin<G::Timestamp, S1, D1>,
+            &mut Bin<G::Timestamp, S2, D2>,
+            &mut OutputHandle<G::Timestamp, D3, Tee<G::Timestamp, D3>>) + 'static,    // state update logic
+        F2: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D2)>,
+            &mut Bin<G::Timestamp, S1, D1>,
+            &mut Bin<G::Timestamp, S2, D2>,
+            &mut OutputHandle<G::Timestamp, D3, Tee<G::Timestamp, D3>>) + 'static,    // state update logic
+    >(&self, control: &Stream<G, Control>, other: &Stream<G, D2>, key1: B1, key2: B2, name: &str, fold1: F1, fold2: F2) -> Stream<G, D3>
+    {
+        StatefulBuilder::new(name, control).binary(self, other, key1, key2, fold1, fold2)
+    }
+
+    fn stateful_binary_input<
+        D2: ExchangeData+Eq,                         // input type
+        D3: Data,                                    // output type
+        N1: ExchangeData,
+        N2: ExchangeData,
+        B1: Fn(&D1)->u64+'static,
+        B2: Fn(&D2)->u64+'static,
+        S1: Clone+IntoIterator<Item=W1>+Extend<W1>+Default+'static,
+        S2: Clone+IntoIterator<Item=W2>+Extend<W2>+Default+'static,
+        W1: ExchangeData,                            // State format on the wire
+        W2: ExchangeData,                            // State format on the wire
+        F1: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, N1)>,
+            &mut Bin<G::Timestamp, S1, N1>,
+            &mut Bin<G::Timestamp, S2, N2>,
+            &mut OutputHandle<G::Timestamp, D3, Tee<G::Timestamp, D3>>) + 'static,    // state update logic
+        F2: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, N2)>,
+            &mut Bin<G::Timestamp, S1, N1>,
+            &mut Bin<G::Timestamp, S2, N2>,
+            &mut OutputHandle<G::Timestamp, D3, Tee<G::Timestamp, D3>>) + 'static,    // state update logic
+        C1: FnMut(&mut State<G::Timestamp, S1, N1>,
+            &Capability<G::Timestamp>,
+            G::Timestamp,
+            RefOrMut<Vec<(usize, Key, D1)>>,
+            &mut OutputHandle<G::Timestamp, D3, Tee<G::Timestamp, D3>>) + 'static,
+        C2: FnMut(&mut State<G::Timestamp, S2, N2>,
+            &Capability<G::Timestamp>,
+            G::Timestamp,
+            RefOrMut<Vec<(usize, Key, D2)>>,
+            &mut OutputHandle<G::Timestamp, D3, Tee<G::Timestamp, D3>>) + 'static,
+    >(&self, control: &Stream<G, Control>, other: &Stream<G, D2>, key1: B1, key2: B2, name: &str, consume1: C1, consume2: C2, fold1: F1, fold2: F2) -> Stream<G, D3>
+    {
+        StatefulBuilder::new(name, control).binary_input(self, other, key1, key2, consume1, consume2, fold1, fold2)
+    }
+
+    fn stateful_nary<
+        D2: Data,                                    // output type
+        B: Fn(usize, &D1)->u64+'static,
+        S: Clone+IntoIterator<Item=W>+Extend<W>+Default+'static,
+        W: ExchangeData,                            // State format on the wire
+        F: FnMut(&Capability<G::Timestamp>,
+            usize,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut [&mut Bin<G::Timestamp, S, D1>],
+            &mut OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>) + 'static,    // state update logic
+    >(&self, control: &Stream<G, Control>, others: &[Stream<G, D1>], key: B, name: &str, fold: F) -> Stream<G, D2>
+    {
+        let mut inputs = vec![self.clone()];
+        inputs.extend(others.iter().cloned());
+        StatefulBuilder::new(name, control).nary(&inputs, key, fold)
+    }
+
+    fn stateful_unary_outputs<
+        D2: Data,                                    // output type
+        B: Fn(&D1)->u64+'static,
+        S: Clone+IntoIterator<Item=W>+Extend<W>+Default+'static,
+        W: ExchangeData,                            // State format on the wire
+        F: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut Bin<G::Timestamp, S, D1>,
+            &mut OutputHandle<G::Timestamp, (usize, D2), Tee<G::Timestamp, (usize, D2)>>) + 'static,    // state update logic
+    >(&self, control: &Stream<G, Control>, key: B, name: &str, outputs: usize, fold: F) -> Vec<Stream<G, D2>>
+    {
+        StatefulBuilder::new(name, control).unary_outputs(self, key, outputs, fold)
+    }
+
+    fn stateful_binary_outputs<
+        D2: ExchangeData+Eq,                         // input type
+        D3: Data,                                    // output type
+        B1: Fn(&D1)->u64+'static,
+        B2: Fn(&D2)->u64+'static,
+        S1: Clone+IntoIterator<Item=W1>+Extend<W1>+Default+'static,
+        S2: Clone+IntoIterator<Item=W2>+Extend<W2>+Default+'static,
+        W1: ExchangeData,                            // State format on the wire
+        W2: ExchangeData,                            // State format on the wire
+        F1: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut Bin<G::Timestamp, S1, D1>,
+            &mut Bin<G::Timestamp, S2, D2>,
+            &mut OutputHandle<G::Timestamp, (usize, D3), Tee<G::Timestamp, (usize, D3)>>) + 'static,    // state update logic
+        F2: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D2)>,
+            &mut Bin<G::Timestamp, S1, D1>,
+            &mut Bin<G::Timestamp, S2, D2>,
+            &mut OutputHandle<G::Timestamp, (usize, D3), Tee<G::Timestamp, (usize, D3)>>) + 'static,    // state update logic
+    >(&self, control: &Stream<G, Control>, other: &Stream<G, D2>, key1: B1, key2: B2, name: &str, outputs: usize, fold1: F1, fold2: F2) -> Vec<Stream<G, D3>>
     {
-        let stateful = self.stateful(key, control);
+        StatefulBuilder::new(name, control).binary_outputs(self, other, key1, key2, outputs, fold1, fold2)
+    }
+
+    fn distribute<B1>(&self, control: &Stream<G, Control>, key: B1, name: &str) -> Stream<G, (usize, Key, D1)>
+        where
+            B1: Fn(&D1)->u64+'static,
+    {
+        let mut data_vec = vec![];
+        self.stateful_unary_input::<_, (), _, Vec<()>, _, _, _>(control, key, name, move |_state, cap, _time, data, output| {
+            data.swap(&mut data_vec);
+            output.session(&cap).give_vec(&mut data_vec);
+        }, |_cap, _data, _bin, _output| {})
+    }
+}
+
+/// Collects the name, control stream and configuration of a stateful operator, and builds unary,
+/// binary or n-ary operators from it.
+///
+/// Options default to those of `StatefulConfig::default()`. Key functions and state update logic
+/// are provided when building, the state type is inferred from the latter.
+pub struct StatefulBuilder<G: Scope>
+    where
+        G::Timestamp: TotalOrder,
+{
+    name: String,
+    control: Stream<G, Control>,
+    config: StatefulConfig,
+}
+
+impl<G: Scope> StatefulBuilder<G>
+    where
+        G::Timestamp: TotalOrder,
+{
+    /// Start building an operator called `name` that is reconfigured by `control`.
+    pub fn new(name: &str, control: &Stream<G, Control>) -> Self {
+        StatefulBuilder {
+            name: name.to_owned(),
+            control: control.clone(),
+            config: Default::default(),
+        }
+    }
+
+    /// Replace the configuration.
+    pub fn config(mut self, config: StatefulConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Select how the operator routes its input.
+    pub fn mode(mut self, mode: StatefulMode) -> Self {
+        self.config = self.config.with_mode(mode);
+        self
+    }
+
+    /// Assign bins to workers according to `map` from the start.
+    pub fn initial_map(mut self, map: Vec<usize>) -> Self {
+        self.config = self.config.with_initial_map(map);
+        self
+    }
+
+    /// Assign bins to workers initially by calling `placement` with each bin and the number of peers.
+    pub fn placement<P: Fn(BinId, usize) -> usize + 'static>(mut self, placement: P) -> Self {
+        self.config = self.config.with_placement(placement);
+        self
+    }
+
+    /// Limit the bytes kept in memory for data waiting on the control input.
+    pub fn stash_limit(mut self, bytes: usize) -> Self {
+        self.config = self.config.with_stash_limit(bytes);
+        self
+    }
+
+    /// Select the directory to spill stashed data to.
+    pub fn spill_directory<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.config = self.config.with_spill_directory(directory);
+        self
+    }
+
+    /// Build an operator with a single input, see `StatefulOperator::stateful_unary`.
+    pub fn unary<
+        D1: ExchangeData+Eq,
+        D2: Data,                                    // output type
+        B: Fn(&D1)->u64+'static,
+        S: Clone+IntoIterator<Item=W>+Extend<W>+Default+'static,
+        W: ExchangeData,                            // State format on the wire
+        F: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut Bin<G::Timestamp, S, D1>,
+            &mut OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>) + 'static,    // state update logic
+    >(&self, input: &Stream<G, D1>, key: B, mut fold: F) -> Stream<G, D2> {
+        let stateful = input.stateful_with_config(key, &self.control, &self.config);
         let states = stateful.state.clone();
 
-        let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
+        let mut builder = OperatorBuilder::new(self.name.clone(), input.scope());
 
         let mut input = builder.new_input(&stateful.stream, Exchange::new(move |&(target, _key, _)| target as u64));
         let mut input_state = builder.new_input(&stateful.state_stream, Exchange::new(move |&(target, _)| target as u64));
@@ -194,7 +495,10 @@ impl<G, D1> StatefulOperator<G, D1> for Stream<G, D1>
         stream
     }
 
-    fn stateful_unary_input<
+    /// Build an operator with a single input and input transformation, see
+    /// `StatefulOperator::stateful_unary_input`.
+    pub fn unary_input<
+        D1: ExchangeData+Eq,
         D2: Data,                                    // output type
         N: ExchangeData+Eq,
         B: Fn(&D1)->u64+'static,
@@ -209,12 +513,11 @@ impl<G, D1> StatefulOperator<G, D1> for Stream<G, D1>
             G::Timestamp,
             RefOrMut<Vec<(usize, Key, D1)>>,
             &mut OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>) + 'static,
-    >(&self, control: &Stream<G, Control>, key: B, name: &str, mut consume: C, mut fold: F) -> Stream<G, D2>
-    {
-        let stateful = self.stateful(key, control);
+    >(&self, input: &Stream<G, D1>, key: B, mut consume: C, mut fold: F) -> Stream<G, D2> {
+        let stateful = input.stateful_with_config(key, &self.control, &self.config);
         let states = stateful.state.clone();
 
-        let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
+        let mut builder = OperatorBuilder::new(self.name.clone(), input.scope());
 
         let mut input = builder.new_input(&stateful.stream, Exchange::new(move |&(target, _key, _)| target as u64));
         let mut input_state = builder.new_input(&stateful.state_stream, Exchange::new(move |&(target, _)| target as u64));
@@ -238,14 +541,14 @@ impl<G, D1> StatefulOperator<G, D1> for Stream<G, D1>
                 }
                 // stash each input and request a notification when ready
                 while let Some((cap, data)) = input.next() {
-//                    if !frontiers[0].less_than(time.time()) && !frontiers[1].less_equal(time.time()) {
-//                        consume(&mut states, time.retain(), data, &mut output_handle);
-//                    } else {
+    //                    if !frontiers[0].less_than(time.time()) && !frontiers[1].less_equal(time.time()) {
+    //                        consume(&mut states, time.retain(), data, &mut output_handle);
+    //                    } else {
                         let mut data_buffer = vec![];
                         data.swap(&mut data_buffer);
                         let time = cap.time().clone();
                         notificator.notify_at_data(&cap.retain(), time, data_buffer);
-//                    }
+    //                    }
                 }
 
                 if let Some(cap) = notificator.drain(&[&frontiers[0], &frontiers[1]], &mut not_drain) {
@@ -268,7 +571,9 @@ impl<G, D1> StatefulOperator<G, D1> for Stream<G, D1>
         stream
     }
 
-    fn stateful_binary<
+    /// Build an operator with two inputs, see `StatefulOperator::stateful_binary`.
+    pub fn binary<
+        D1: ExchangeData+Eq,
         D2: ExchangeData+Eq,                         // input type
         D3: Data,                                    // output type
         B1: Fn(&D1)->u64+'static,
@@ -287,13 +592,11 @@ impl<G, D1> StatefulOperator<G, D1> for Stream<G, D1>
             &mut Bin<G::Timestamp, S1, D1>,
             &mut Bin<G::Timestamp, S2, D2>,
             &mut OutputHandle<G::Timestamp, D3, Tee<G::Timestamp, D3>>) + 'static,    // state update logic
-    >(&self, control: &Stream<G, Control>, other: &Stream<G, D2>, key1: B1, key2: B2, name: &str, fold1: F1, fold2: F2) -> Stream<G, D3>
-    {
-
+    >(&self, input1: &Stream<G, D1>, input2: &Stream<G, D2>, key1: B1, key2: B2, fold1: F1, fold2: F2) -> Stream<G, D3> {
         let mut data1_buffer = vec![];
         let mut data2_buffer = vec![];
 
-        self.stateful_binary_input(control, other, key1, key2, name,
+        self.binary_input(input1, input2, key1, key2,
             move |state, cap, time, data, _output| {
                 data.swap(&mut data1_buffer);
                 for (_worker, key_id, d) in data1_buffer.drain(..) {
@@ -308,7 +611,10 @@ impl<G, D1> StatefulOperator<G, D1> for Stream<G, D1>
            }, fold1, fold2)
     }
 
-    fn stateful_binary_input<
+    /// Build an operator with two inputs and input transformation, see
+    /// `StatefulOperator::stateful_binary_input`.
+    pub fn binary_input<
+        D1: ExchangeData+Eq,
         D2: ExchangeData+Eq,                         // input type
         D3: Data,                                    // output type
         N1: ExchangeData,
@@ -339,14 +645,13 @@ impl<G, D1> StatefulOperator<G, D1> for Stream<G, D1>
             G::Timestamp,
             RefOrMut<Vec<(usize, Key, D2)>>,
             &mut OutputHandle<G::Timestamp, D3, Tee<G::Timestamp, D3>>) + 'static,
-    >(&self, control: &Stream<G, Control>, other: &Stream<G, D2>, key1: B1, key2: B2, name: &str, mut consume1: C1, mut consume2: C2, mut fold1: F1, mut fold2: F2) -> Stream<G, D3>
-    {
-        let stateful1 = self.stateful(key1, &control);
-        let stateful2 = other.stateful(key2, &control);
+    >(&self, input1: &Stream<G, D1>, input2: &Stream<G, D2>, key1: B1, key2: B2, mut consume1: C1, mut consume2: C2, mut fold1: F1, mut fold2: F2) -> Stream<G, D3> {
+        let stateful1 = input1.stateful_with_config(key1, &self.control, &self.config);
+        let stateful2 = input2.stateful_with_config(key2, &self.control, &self.config);
         let states1 = stateful1.state.clone();
         let states2 = stateful2.state.clone();
 
-        let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
+        let mut builder = OperatorBuilder::new(self.name.clone(), input1.scope());
 
         let mut input1 = builder.new_input(&stateful1.stream, Exchange::new(move |&(target, _key, _)| target as u64));
         let mut input1_state = builder.new_input(&stateful1.state_stream, Exchange::new(move |&(target, _)| target as u64));
@@ -426,15 +731,152 @@ impl<G, D1> StatefulOperator<G, D1> for Stream<G, D1>
         stream
     }
 
-    fn distribute<B1>(&self, control: &Stream<G, Control>, key: B1, name: &str) -> Stream<G, (usize, Key, D1)>
-        where
-            B1: Fn(&D1)->u64+'static,
-    {
-        let mut data_vec = vec![];
-        self.stateful_unary_input::<_, (), _, Vec<()>, _, _, _>(control, key, name, move |_state, cap, _time, data, output| {
-            data.swap(&mut data_vec);
-            output.session(&cap).give_vec(&mut data_vec);
-        }, |_cap, _data, _bin, _output| {})
+    /// Build an operator with one or more inputs of the same record and state type, see
+    /// `StatefulOperator::stateful_nary`. Panics if `inputs` is empty.
+    pub fn nary<
+        D1: ExchangeData+Eq,
+        D2: Data,                                    // output type
+        B: Fn(usize, &D1)->u64+'static,
+        S: Clone+IntoIterator<Item=W>+Extend<W>+Default+'static,
+        W: ExchangeData,                            // State format on the wire
+        F: FnMut(&Capability<G::Timestamp>,
+            usize,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut [&mut Bin<G::Timestamp, S, D1>],
+            &mut OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>) + 'static,    // state update logic
+    >(&self, inputs: &[Stream<G, D1>], key: B, mut fold: F) -> Stream<G, D2> {
+        assert!(!inputs.is_empty(), "StatefulBuilder::nary requires at least one input");
+        let key = Rc::new(key);
+
+        let statefuls: Vec<_> = inputs.iter().enumerate().map(|(index, input)| {
+            let key = Rc::clone(&key);
+            input.stateful_with_config(move |d: &D1| (*key)(index, d), &self.control, &self.config)
+        }).collect();
+
+        let mut builder = OperatorBuilder::new(self.name.clone(), inputs[0].scope());
+
+        // Inputs `2 * i` and `2 * i + 1` are the data and state inputs of stream `i`
+        let mut inputs = Vec::with_capacity(statefuls.len());
+        let mut inputs_state = Vec::with_capacity(statefuls.len());
+        let mut states = Vec::with_capacity(statefuls.len());
+        let mut feedbacks = Vec::with_capacity(statefuls.len());
+        for stateful in statefuls {
+            inputs.push(builder.new_input(&stateful.stream, Exchange::new(move |&(target, _key, _)| target as u64)));
+            inputs_state.push(builder.new_input(&stateful.state_stream, Exchange::new(move |&(target, _)| target as u64)));
+            states.push(stateful.state.clone());
+            feedbacks.push(stateful.feedback);
+        }
+        let (mut output, stream) = builder.new_output();
+
+        let mut not_drain = Vec::new();
+        let mut bin_drain = Vec::new();
+
+        builder.build(move |_capability| {
+            let mut state_update_buffer = vec![];
+
+            let mut notificators: Vec<_> = states.iter().map(|_| Notificator::new()).collect();
+
+            move |frontiers| {
+                let mut output_handle = output.activate();
+
+                let mut states: Vec<_> = states.iter().map(|state| state.borrow_mut()).collect();
+
+                for (state, input_state) in states.iter_mut().zip(inputs_state.iter_mut()) {
+                    while let Some((time, data)) = input_state.next() {
+                        data.swap(&mut state_update_buffer);
+                        apply_state_updates(state, &time.retain(), state_update_buffer.drain(..))
+                    }
+                }
+
+                // stash each input and request a notification when ready
+                for (notificator, input) in notificators.iter_mut().zip(inputs.iter_mut()) {
+                    while let Some((cap, data)) = input.next() {
+                        let mut data_buffer = vec![];
+                        data.swap(&mut data_buffer);
+                        let time = cap.time().clone();
+                        notificator.notify_at_data(&cap.retain(), time, data_buffer);
+                    }
+                }
+
+                for (index, notificator) in notificators.iter_mut().enumerate() {
+                    if let Some(cap) = notificator.drain(&[&frontiers[2 * index], &frontiers[2 * index + 1]], &mut not_drain) {
+                        for (time, mut keyed_data) in not_drain.drain(..) {
+                            for (_, key_id, d) in keyed_data.drain(..) {
+                                states[index].get(key_id).notificator().notify_at_data(&cap, time.clone(), d);
+                            }
+                        }
+                    }
+                }
+
+                // go through each time with data, bins of all inputs are co-located
+                let all_frontiers: Vec<&MutableAntichain<G::Timestamp>> = frontiers.iter().collect();
+                for bin in 0..states[0].bins.len() {
+                    if states.iter().any(|state| state.bins[bin].is_none()) {
+                        continue;
+                    }
+                    let mut bins: Vec<&mut Bin<G::Timestamp, S, D1>> = states.iter_mut().map(|state| state.bins[bin].as_mut().unwrap()).collect();
+                    for index in 0..bins.len() {
+                        if let Some(cap) = bins[index].notificator().drain(&all_frontiers, &mut bin_drain) {
+                            fold(&cap, index, &mut bin_drain, &mut bins, &mut output_handle);
+                        }
+                    }
+                }
+            }
+        });
+        let progress_stream = stream.filter(|_| false).map(|_| ());
+        for feedback in feedbacks {
+            progress_stream.connect_loop(feedback);
+        }
+        stream
+    }
+
+    /// Build an operator with a single input and `outputs` outputs, see
+    /// `StatefulOperator::stateful_unary_outputs`.
+    pub fn unary_outputs<
+        D1: ExchangeData+Eq,
+        D2: Data,                                    // output type
+        B: Fn(&D1)->u64+'static,
+        S: Clone+IntoIterator<Item=W>+Extend<W>+Default+'static,
+        W: ExchangeData,                            // State format on the wire
+        F: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut Bin<G::Timestamp, S, D1>,
+            &mut OutputHandle<G::Timestamp, (usize, D2), Tee<G::Timestamp, (usize, D2)>>) + 'static,    // state update logic
+    >(&self, input: &Stream<G, D1>, key: B, outputs: usize, fold: F) -> Vec<Stream<G, D2>> {
+        partition_outputs(&self.unary(input, key, fold), outputs)
     }
 
+    /// Build an operator with two inputs and `outputs` outputs, see
+    /// `StatefulOperator::stateful_binary_outputs`.
+    pub fn binary_outputs<
+        D1: ExchangeData+Eq,
+        D2: ExchangeData+Eq,                         // input type
+        D3: Data,                                    // output type
+        B1: Fn(&D1)->u64+'static,
+        B2: Fn(&D2)->u64+'static,
+        S1: Clone+IntoIterator<Item=W1>+Extend<W1>+Default+'static,
+        S2: Clone+IntoIterator<Item=W2>+Extend<W2>+Default+'static,
+        W1: ExchangeData,                            // State format on the wire
+        W2: ExchangeData,                            // State format on the wire
+        F1: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D1)>,
+            &mut Bin<G::Timestamp, S1, D1>,
+            &mut Bin<G::Timestamp, S2, D2>,
+            &mut OutputHandle<G::Timestamp, (usize, D3), Tee<G::Timestamp, (usize, D3)>>) + 'static,    // state update logic
+        F2: FnMut(&Capability<G::Timestamp>,
+            &mut Vec<(G::Timestamp, D2)>,
+            &mut Bin<G::Timestamp, S1, D1>,
+            &mut Bin<G::Timestamp, S2, D2>,
+            &mut OutputHandle<G::Timestamp, (usize, D3), Tee<G::Timestamp, (usize, D3)>>) + 'static,    // state update logic
+    >(&self, input1: &Stream<G, D1>, input2: &Stream<G, D2>, key1: B1, key2: B2, outputs: usize, fold1: F1, fold2: F2) -> Vec<Stream<G, D3>> {
+        partition_outputs(&self.binary(input1, input2, key1, key2, fold1, fold2), outputs)
+    }
+}
+
+/// Split records tagged with an output index into `outputs` streams.
+fn partition_outputs<G: Scope, D: Data>(stream: &Stream<G, (usize, D)>, outputs: usize) -> Vec<Stream<G, D>> {
+    stream.partition(outputs as u64, move |(index, data)| {
+        assert!(index < outputs, "Output index {} out of range, there are {} outputs", index, outputs);
+        (index as u64, data)
+    })
 }
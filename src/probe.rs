@@ -0,0 +1,110 @@
+//! Tracking of migration completion.
+//!
+//! A [`MigrationProbe`] relates the sequence numbers of control batches to the times at which they
+//! take effect. A configuration is complete once the outputs of the stateful operators it applies
+//! to have advanced beyond its time: at this point all workers have installed it, all moved bins
+//! have arrived at their new owners and all data up to its time has been processed.
+//!
+//! Completion only reflects progress, not whether a configuration was installed. Batches that the
+//! stateful operators withdraw or drop as invalid are complete once the outputs have advanced
+//! beyond their time, as the operators will not act on them anymore.
+//!
+//! The probe only remembers pending configurations: once a prefix of the observed configurations
+//! is complete, their times are forgotten.
+//!
+//! [`MigrationProbe`]: struct.MigrationProbe.html
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use timely::dataflow::{ProbeHandle, Stream, Scope};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
+use timely::progress::Timestamp;
+
+use ::Control;
+
+/// Reports, per control sequence number, whether the configuration has been installed everywhere.
+///
+/// Construct it from the control stream and attach its `handle` to the outputs of all stateful
+/// operators sharing that control stream, e.g. with `probe_with`. Until the handle has been
+/// requested for attaching, no configuration is reported complete.
+pub struct MigrationProbe<T: Timestamp> {
+    sequences: Rc<RefCell<Sequences<T>>>,
+    handle: ProbeHandle<T>,
+    attached: bool,
+}
+
+/// Configurations observed by a `MigrationProbe`.
+struct Sequences<T> {
+    /// Sequence numbers and the times they take effect of configurations that are not known to be
+    /// complete, ordered by sequence number.
+    pending: Vec<(u64, T)>,
+    /// The largest sequence number such that it and all observed configurations before it are
+    /// complete.
+    completed: Option<u64>,
+}
+
+impl<T: Timestamp> MigrationProbe<T> {
+    /// Construct a probe observing the sequence numbers of `control`.
+    pub fn new<G: Scope<Timestamp=T>>(control: &Stream<G, Control>) -> Self {
+        let sequences = Rc::new(RefCell::new(Sequences { pending: Vec::new(), completed: None }));
+        let sequences_sink = Rc::clone(&sequences);
+        let mut data_buffer = Vec::new();
+        let _: Stream<G, ()> = control.unary(Pipeline, "MigrationProbe", move |_cap, _info| {
+            move |input, _output| {
+                input.for_each(|time, data| {
+                    data.swap(&mut data_buffer);
+                    let mut sequences = sequences_sink.borrow_mut();
+                    for control in data_buffer.drain(..) {
+                        if sequences.completed.map_or(false, |completed| control.sequence <= completed) {
+                            continue;
+                        }
+                        if let Err(position) = sequences.pending.binary_search_by_key(&control.sequence, |&(sequence, _)| sequence) {
+                            sequences.pending.insert(position, (control.sequence, time.time().clone()));
+                        }
+                    }
+                });
+            }
+        });
+        Self { sequences, handle: ProbeHandle::new(), attached: false }
+    }
+
+    /// The probe handle to attach to the outputs of the stateful operators.
+    pub fn handle(&mut self) -> &mut ProbeHandle<T> {
+        self.attached = true;
+        &mut self.handle
+    }
+
+    /// The time at which the configuration with `sequence` takes effect, if it has been observed
+    /// and is pending.
+    pub fn time(&self, sequence: u64) -> Option<T> {
+        let sequences = self.sequences.borrow();
+        sequences.pending.binary_search_by_key(&sequence, |&(sequence, _)| sequence).ok().map(|position| sequences.pending[position].1.clone())
+    }
+
+    /// Returns `true` if the configuration with `sequence` has been observed and is complete.
+    ///
+    /// This is also the case for withdrawn and dropped configurations once the outputs have
+    /// advanced beyond their time.
+    pub fn is_complete(&self, sequence: u64) -> bool {
+        if self.completed().map_or(false, |completed| sequence <= completed) {
+            return true;
+        }
+        self.attached && self.time(sequence).map_or(false, |time| !self.handle.less_equal(&time))
+    }
+
+    /// The largest sequence number such that it and all observed configurations before it are
+    /// complete.
+    pub fn completed(&self) -> Option<u64> {
+        let mut sequences = self.sequences.borrow_mut();
+        if self.attached {
+            // Forget the complete prefix of pending configurations
+            let complete = sequences.pending.iter().take_while(|&&(_, ref time)| !self.handle.less_equal(time)).count();
+            if complete > 0 {
+                sequences.completed = Some(sequences.pending[complete - 1].0);
+                sequences.pending.drain(..complete);
+            }
+        }
+        sequences.completed
+    }
+}
@@ -0,0 +1,160 @@
+//! Bounded stash for data awaiting the control frontier.
+//!
+//! The stash keeps batches in memory up to a byte limit. Batches beyond the limit are spilled to a
+//! file per time in a spill directory and read back once the time is released.
+use std::cell::Cell;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use abomonation::{decode, encode, measure};
+use fnv::FnvHashMap as HashMap;
+
+use timely::ExchangeData;
+
+/// Distinguishes spill files of stashes within a process.
+static STASH_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Sizes of stashed data, shared between all stashes of a configuration.
+#[derive(Debug, Default)]
+pub struct StashMetrics {
+    /// Bytes currently held in memory
+    pub in_memory: Cell<usize>,
+    /// Bytes currently spilled to disk
+    pub spilled: Cell<usize>,
+}
+
+/// A file holding spilled batches of a single time.
+struct SpillFile {
+    path: PathBuf,
+    file: File,
+    bytes: usize,
+}
+
+/// Batches of data per time, spilling to disk beyond a memory limit.
+pub struct Stash<T: Eq+Hash, V> {
+    limit: Option<usize>,
+    directory: PathBuf,
+    prefix: String,
+    metrics: Rc<StashMetrics>,
+    in_memory: usize,
+    memory: HashMap<T, Vec<(usize, Vec<V>)>>,
+    spilled: HashMap<T, SpillFile>,
+    spill_count: usize,
+}
+
+impl<T: Eq+Hash+Clone, V: ExchangeData> Stash<T, V> {
+    /// Construct a stash keeping at most `limit` bytes in memory and spilling to `directory`.
+    pub fn new(limit: Option<usize>, directory: PathBuf, metrics: Rc<StashMetrics>) -> Self {
+        let prefix = format!("megaphone-stash-{}-{}", process::id(), STASH_ID.fetch_add(1, Ordering::SeqCst));
+        Stash {
+            limit,
+            directory,
+            prefix,
+            metrics,
+            in_memory: 0,
+            memory: Default::default(),
+            spilled: Default::default(),
+            spill_count: 0,
+        }
+    }
+
+    /// Stash a batch of data for `time`.
+    pub fn push(&mut self, time: T, data: Vec<V>) {
+        let bytes = measure(&data);
+        if self.limit.map_or(true, |limit| self.in_memory + bytes <= limit) {
+            self.in_memory += bytes;
+            self.metrics.in_memory.set(self.metrics.in_memory.get() + bytes);
+            self.memory.entry(time).or_insert_with(Vec::new).push((bytes, data));
+        } else {
+            self.spill(time, &data);
+        }
+    }
+
+    /// Append `data` to the spill file of `time`.
+    ///
+    /// Each frame consists of the length of the encoded data as a little-endian `u64`, followed by
+    /// the data.
+    fn spill(&mut self, time: T, data: &Vec<V>) {
+        let mut buffer = Vec::with_capacity(8 + measure(data));
+        buffer.extend_from_slice(&[0; 8]);
+        unsafe { encode(data, &mut buffer).expect("Failed to encode stashed data"); }
+        let length = (buffer.len() - 8) as u64;
+        for (index, byte) in buffer[..8].iter_mut().enumerate() {
+            *byte = (length >> (8 * index)) as u8;
+        }
+
+        if !self.spilled.contains_key(&time) {
+            let path = self.directory.join(format!("{}-{}", self.prefix, self.spill_count));
+            self.spill_count += 1;
+            let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)
+                .unwrap_or_else(|e| panic!("Failed to create spill file {}: {}", path.display(), e));
+            self.spilled.insert(time.clone(), SpillFile { path, file, bytes: 0 });
+        }
+        let spill_file = self.spilled.get_mut(&time).unwrap();
+        spill_file.file.write_all(&buffer)
+            .unwrap_or_else(|e| panic!("Failed to write spill file {}: {}", spill_file.path.display(), e));
+        spill_file.bytes += buffer.len();
+        self.metrics.spilled.set(self.metrics.spilled.get() + buffer.len());
+    }
+
+    /// Returns `true` if the stash holds data for `time`.
+    pub fn contains(&self, time: &T) -> bool {
+        self.memory.contains_key(time) || self.spilled.contains_key(time)
+    }
+
+    /// Remove and return all batches stashed for `time`.
+    pub fn take(&mut self, time: &T) -> Vec<Vec<V>> {
+        let mut batches = Vec::new();
+        if let Some(memory) = self.memory.remove(time) {
+            for (bytes, data) in memory {
+                self.in_memory -= bytes;
+                self.metrics.in_memory.set(self.metrics.in_memory.get() - bytes);
+                batches.push(data);
+            }
+        }
+        if let Some(spill_file) = self.spilled.remove(time) {
+            let SpillFile { path, file, bytes } = spill_file;
+            drop(file);
+            let mut contents = Vec::with_capacity(bytes);
+            File::open(&path).and_then(|mut file| file.read_to_end(&mut contents))
+                .unwrap_or_else(|e| panic!("Failed to read spill file {}: {}", path.display(), e));
+            let _ = fs::remove_file(&path);
+            self.metrics.spilled.set(self.metrics.spilled.get() - bytes);
+
+            // Frames are decoded from a copy in `u64` words, as aligned as data exchanged between
+            // workers, independent of their position in the file
+            let mut words = Vec::new();
+            let mut offset = 0;
+            while offset + 8 <= contents.len() {
+                let length = contents[offset..offset + 8].iter().enumerate().fold(0, |length, (index, &byte)| length | (byte as u64) << (8 * index)) as usize;
+                let (start, end) = (offset + 8, offset + 8 + length);
+                words.clear();
+                words.resize((length + 7) / 8, 0u64);
+                let frame = unsafe { slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, length) };
+                frame.copy_from_slice(&contents[start..end]);
+                {
+                    let (data, _) = unsafe { decode::<Vec<V>>(frame) }.expect("Failed to decode spilled data");
+                    batches.push(data.clone());
+                }
+                offset = end;
+            }
+        }
+        batches
+    }
+}
+
+impl<T: Eq+Hash, V> Drop for Stash<T, V> {
+    fn drop(&mut self) {
+        self.metrics.in_memory.set(self.metrics.in_memory.get() - self.in_memory);
+        for (_time, spill_file) in self.spilled.drain() {
+            self.metrics.spilled.set(self.metrics.spilled.get() - spill_file.bytes);
+            let _ = fs::remove_file(&spill_file.path);
+        }
+    }
+}
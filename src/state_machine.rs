@@ -1,15 +1,60 @@
 //! General purpose state transition operator, implemented with Megaphone.
 use std::hash::Hash;
 
-use fnv::FnvHashMap as HashMap;
-
 use timely::ExchangeData;
 use timely::dataflow::{Stream, Scope};
 use timely::Data;
 
+use keyed::{KeyedState, StateContainer, TimedState};
 use operator::StatefulOperator;
 use ::Control;
 
+/// An event delivered to the `fold` of a state machine with timers.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StateMachineEvent<V> {
+    /// A record for the key.
+    Record(V),
+    /// The key's timer fired.
+    Timeout,
+}
+
+/// Provides access to the timer of a key from within a state machine's `fold`.
+///
+/// Each key has at most one pending timer. Scheduling replaces a pending timer, and removing the
+/// key's state cancels it. Timers are kept in the bin's notificator under a timer key that is
+/// unique within the bin, and migrate with the bin.
+pub struct Timer<T> {
+    time: T,
+    request: Option<Option<T>>,
+}
+
+impl<T: Clone+Ord+::std::fmt::Debug> Timer<T> {
+    /// The time of the current event.
+    pub fn time(&self) -> &T {
+        &self.time
+    }
+
+    /// Schedule a `Timeout` event at `time`, which must not be earlier than the current time.
+    pub fn schedule_at(&mut self, time: T) {
+        assert!(time >= self.time, "Timer scheduled at {:?}, before the current time {:?}", time, self.time);
+        self.request = Some(Some(time));
+    }
+
+    /// Cancel the pending timer, if any.
+    pub fn cancel(&mut self) {
+        self.request = Some(None);
+    }
+}
+
+/// Notifications of the state machine with timers.
+#[derive(Abomonation, Clone, Debug, Eq, PartialEq)]
+enum Notification<K, V> {
+    /// A record to apply
+    Record(K, V),
+    /// A timer of the key fired
+    Timer(K),
+}
+
 /// Provide a general-purpose state machine operator that can be migrated without changes to the
 /// `fold` implementation.
 pub trait BinnedStateMachine<S, K, V, D>
@@ -35,6 +80,32 @@ where
         F: Fn(&K, V, &mut D)->(bool, I)+'static,    // state update logic
         H: Fn(&K)->u64+'static,                     // "hash" function for keys
     >(&self, fold: F, hash: H, control: &Stream<S, Control>) -> Stream<S, R> where S::Timestamp : Hash+Eq;
+
+    /// Like `stateful_state_machine`, but keeps the states of each bin in a container of type `C`.
+    ///
+    /// The container can be any `StateContainer`, for example a `KeyedState`, a `BTreeMap`, or a
+    /// `DenseState` for small integer keys, and is usually selected with a turbofish:
+    /// `stateful_state_machine_in::<DenseState<_>, _, _, _, _>(fold, hash, control)`.
+    fn stateful_state_machine_in<
+        C: StateContainer<K, D>,                    // per-bin container of states
+        R: Data,                                    // output type
+        I: IntoIterator<Item=R>,                    // type of output iterator
+        F: Fn(&K, V, &mut D)->(bool, I)+'static,    // state update logic
+        H: Fn(&K)->u64+'static,                     // "hash" function for keys
+    >(&self, fold: F, hash: H, control: &Stream<S, Control>) -> Stream<S, R> where S::Timestamp : Hash+Eq;
+
+    /// Like `stateful_state_machine`, but `fold` can schedule a timer per key.
+    ///
+    /// `fold` receives the key, a `StateMachineEvent` that is either a record or the timeout of
+    /// the key's timer, the key's state, and a `Timer` to schedule or cancel the key's timer.
+    /// Records and timeouts are applied in time order, with records before timeouts at the same
+    /// time.
+    fn stateful_state_machine_timers<
+        R: Data,                                    // output type
+        I: IntoIterator<Item=R>,                    // type of output iterator
+        F: Fn(&K, StateMachineEvent<V>, &mut D, &mut Timer<S::Timestamp>)->(bool, I)+'static,    // state update logic
+        H: Fn(&K)->u64+'static,                     // "hash" function for keys
+    >(&self, fold: F, hash: H, control: &Stream<S, Control>) -> Stream<S, R> where S::Timestamp : Hash+Eq;
 }
 
 impl<S, K, V, D> BinnedStateMachine<S, K, V, D> for Stream<S, (K, V)>
@@ -51,21 +122,71 @@ where
         F: Fn(&K, V, &mut D) -> (bool, I) + 'static,    // state update logic
         H: Fn(&K)->u64+'static,                     // "hash" function for keys
     >(&self, fold: F, hash: H, control: &Stream<S, Control>) -> Stream<S, R> where S::Timestamp : Hash+Eq {
+        self.stateful_state_machine_in::<KeyedState<K, D>, _, _, _, _>(fold, hash, control)
+    }
+    fn stateful_state_machine_in<
+        C: StateContainer<K, D>,                    // per-bin container of states
+        R: Data,                                    // output type
+        I: IntoIterator<Item=R>,                    // type of output iterator
+        F: Fn(&K, V, &mut D) -> (bool, I) + 'static,    // state update logic
+        H: Fn(&K)->u64+'static,                     // "hash" function for keys
+    >(&self, fold: F, hash: H, control: &Stream<S, Control>) -> Stream<S, R> where S::Timestamp : Hash+Eq {
 
         self.stateful_unary(control, move |(k, _v)| hash(&k), "StateMachine", move |cap, iter, bin, output| {
             let mut session = output.session(&cap);
-            let states: &mut HashMap<_, _> = bin.state();
+            let states: &mut C = bin.state();
             for (_time, (key, val)) in iter.drain(..) {
-                let (remove, output) = {
-                    if !states.contains_key(&key) {
-                        states.insert(key.clone(), Default::default());
-                    }
-                    let state = states.get_mut(&key).unwrap();
-                    fold(&key, val.clone(), state)
-                };
-                if remove { states.remove(&key); }
+                let (remove, output) = fold(&key, val, states.get_or_default(&key));
+                if remove { states.remove_key(&key); }
                 session.give_iterator(output.into_iter());
             }
         })
     }
+    fn stateful_state_machine_timers<
+        R: Data,                                    // output type
+        I: IntoIterator<Item=R>,                    // type of output iterator
+        F: Fn(&K, StateMachineEvent<V>, &mut D, &mut Timer<S::Timestamp>)->(bool, I)+'static,    // state update logic
+        H: Fn(&K)->u64+'static,                     // "hash" function for keys
+    >(&self, fold: F, hash: H, control: &Stream<S, Control>) -> Stream<S, R> where S::Timestamp : Hash+Eq {
+
+        let mut data_buffer = vec![];
+        self.stateful_unary_input(control, move |(k, _v)| hash(&k), "StateMachineTimers", move |state, cap, time, data, _output| {
+            data.swap(&mut data_buffer);
+            for (_worker, key_id, (key, val)) in data_buffer.drain(..) {
+                state.get(key_id).notificator().notify_at_data(cap, time.clone(), Notification::Record(key, val));
+            }
+        }, move |cap, data, bin, output| {
+            // Apply records before timers at the same time
+            data.sort_by_key(|&(ref time, ref event)| (time.clone(), match *event { Notification::Record(..) => 0, Notification::Timer(_) => 1 }));
+            let mut session_cap = cap.clone();
+            for (time, event) in data.drain(..) {
+                if *session_cap.time() != time {
+                    session_cap = cap.delayed(&time);
+                }
+                let (key, event) = match event {
+                    Notification::Record(key, val) => (key, StateMachineEvent::Record(val)),
+                    Notification::Timer(key) => (key, StateMachineEvent::Timeout),
+                };
+                let mut timer = Timer { time: time.clone(), request: None };
+                let (timer_key, remove, output_records) = {
+                    let states: &mut TimedState<_, _> = bin.state();
+                    let (timer_key, state) = states.get_or_insert_with(&key, Default::default);
+                    let (remove, output_records) = fold(&key, event, state, &mut timer);
+                    (timer_key, remove, output_records)
+                };
+                if remove {
+                    let states: &mut TimedState<K, D> = bin.state();
+                    states.remove(&key);
+                    bin.notificator().cancel(timer_key);
+                } else {
+                    match timer.request {
+                        Some(Some(at)) => bin.notificator().notify_at_key(&cap, timer_key, at, Notification::Timer(key)),
+                        Some(None) => { bin.notificator().cancel(timer_key); },
+                        None => {},
+                    }
+                }
+                output.session(&session_cap).give_iterator(output_records.into_iter());
+            }
+        })
+    }
 }
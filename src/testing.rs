@@ -0,0 +1,221 @@
+//! Checking that dataflows produce the same outputs with and without migrations.
+//!
+//! [`MigrationHarness`] runs a dataflow twice on the same input, once under a seeded random
+//! sequence of `Map` and `Move` instructions and once without any migration, and reports the first
+//! time at which the outputs differ as a [`Divergence`]. [`divergences`] and [`verify`] compare two
+//! streams per timestamp and can be used on their own.
+//!
+//! [`MigrationHarness`]: struct.MigrationHarness.html
+//! [`Divergence`]: struct.Divergence.html
+//! [`divergences`]: fn.divergences.html
+//! [`verify`]: fn.verify.html
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+
+use timely::{Data, ExchangeData};
+use timely::communication::Allocate;
+use timely::dataflow::{InputHandle, ProbeHandle, Scope, Stream};
+use timely::dataflow::channels::pact::Exchange;
+use timely::dataflow::operators::{Inspect, Map, Operator, Probe};
+use timely::dataflow::scopes::Child;
+use timely::worker::Worker;
+
+use control::ControlHandle;
+use ::{BinId, Control, ControlInst, BIN_SHIFT};
+
+/// Outputs that differ at a time.
+#[derive(Clone, Debug)]
+pub struct Divergence<T, D> {
+    /// The time at which the outputs differ.
+    pub time: T,
+    /// The sorted outputs of the reference computation.
+    pub expected: Vec<D>,
+    /// The sorted outputs of the computation under test.
+    pub actual: Vec<D>,
+    /// The control batches sent up to and including `time`, if known.
+    pub controls: Vec<(T, Vec<ControlInst>)>,
+}
+
+impl<T: fmt::Debug, D: fmt::Debug> fmt::Display for Divergence<T, D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Outputs diverge at {:?}", self.time)?;
+        writeln!(f, "  expected: {:?}", self.expected)?;
+        write!(f, "  actual:   {:?}", self.actual)?;
+        for &(ref time, ref batch) in &self.controls {
+            write!(f, "\n  control at {:?}: {:?}", time, batch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare `expected` and `actual` per timestamp, producing a `Divergence` for each time at which
+/// their outputs differ as multisets.
+///
+/// All data is compared on worker 0, which is the only worker producing divergences.
+pub fn divergences<S: Scope, D: ExchangeData+Ord>(expected: &Stream<S, D>, actual: &Stream<S, D>) -> Stream<S, Divergence<S::Timestamp, D>> {
+    let mut expected_pending: HashMap<_, Vec<_>> = Default::default();
+    let mut actual_pending: HashMap<_, Vec<_>> = Default::default();
+    let mut data_buffer: Vec<D> = Vec::new();
+    expected.binary_notify(&actual, Exchange::new(|_| 0), Exchange::new(|_| 0), "Divergences", vec![],
+        move |in1, in2, output, not| {
+            in1.for_each(|time, data| {
+                data.swap(&mut data_buffer);
+                expected_pending.entry(time.time().clone()).or_insert_with(Default::default).extend(data_buffer.drain(..));
+                not.notify_at(time.retain());
+            });
+            in2.for_each(|time, data| {
+                data.swap(&mut data_buffer);
+                actual_pending.entry(time.time().clone()).or_insert_with(Default::default).extend(data_buffer.drain(..));
+                not.notify_at(time.retain());
+            });
+            not.for_each(|time, _, _| {
+                let mut expected = expected_pending.remove(time.time()).unwrap_or_default();
+                let mut actual = actual_pending.remove(time.time()).unwrap_or_default();
+                expected.sort();
+                actual.sort();
+                if expected != actual {
+                    output.session(&time).give(Divergence { time: time.time().clone(), expected, actual, controls: Vec::new() });
+                }
+            })
+        }
+    )
+}
+
+/// Compare `correct` and `output` per timestamp, panicking at the first time they differ.
+pub fn verify<S: Scope, D: ExchangeData+Ord+fmt::Debug>(correct: &Stream<S, D>, output: &Stream<S, D>) -> Stream<S, ()> {
+    divergences(correct, output).map(|divergence| -> () { panic!("{}", divergence) })
+}
+
+/// Runs a dataflow with and without a random sequence of migrations and compares the outputs.
+///
+/// The sequence of migrations only depends on the seed and the number of workers, such that a
+/// reported divergence can be reproduced by running the harness again with the same seed.
+#[derive(Clone, Debug)]
+pub struct MigrationHarness {
+    seed: u64,
+    rounds: u64,
+    migration_probability: f64,
+}
+
+impl MigrationHarness {
+    /// Construct a harness generating migrations from `seed`, running 100 rounds by default.
+    pub fn new(seed: u64) -> Self {
+        MigrationHarness {
+            seed,
+            rounds: 100,
+            migration_probability: 0.2,
+        }
+    }
+
+    /// Set the number of rounds, i.e., timestamps, to run.
+    pub fn rounds(mut self, rounds: u64) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Set the probability of a migration in each round.
+    pub fn migration_probability(mut self, probability: f64) -> Self {
+        assert!(probability >= 0. && probability <= 1., "Probability must be within [0, 1], got {}", probability);
+        self.migration_probability = probability;
+        self
+    }
+
+    /// The control batches sent to the computation under test, with the round they are sent in.
+    ///
+    /// Each batch either installs a random map or moves up to four random bins.
+    pub fn plan(&self, peers: usize) -> Vec<(u64, Vec<ControlInst>)> {
+        let mut seed = [0u8; 16];
+        for (index, byte) in seed.iter_mut().enumerate() {
+            *byte = (self.seed >> (8 * (index % 8))) as u8 ^ index as u8;
+        }
+        let mut rng = SmallRng::from_seed(seed);
+        let bins = 1 << BIN_SHIFT;
+        let mut plan = Vec::new();
+        for round in 0..self.rounds {
+            if rng.gen_bool(self.migration_probability) {
+                let batch = if rng.gen_bool(0.5) {
+                    vec![ControlInst::Map((0..bins).map(|_| rng.gen_range(0, peers)).collect())]
+                } else {
+                    (0..rng.gen_range(1, 5)).map(|_| ControlInst::Move(BinId::new(rng.gen_range(0, bins)), rng.gen_range(0, peers))).collect()
+                };
+                plan.push((round, batch));
+            }
+        }
+        plan
+    }
+
+    /// Run the computation constructed by `logic` on `worker`, once with migrations and once
+    /// without.
+    ///
+    /// `input` provides the records this worker introduces in each round. `logic` receives the
+    /// input and control streams and must only use the control stream for its stateful operators.
+    /// Returns the first divergence together with the control batches sent up to its time. Only
+    /// worker 0 observes divergences; all other workers return `Ok`.
+    pub fn run<A, D1, D2, I, L>(&self, worker: &mut Worker<A>, mut input: I, logic: L) -> Result<(), Divergence<u64, D2>>
+    where
+        A: Allocate,
+        D1: Data,
+        D2: ExchangeData+Ord,
+        I: FnMut(u64)->Vec<D1>,
+        L: for<'a> Fn(&Stream<Child<'a, Worker<A>, u64>, D1>, &Stream<Child<'a, Worker<A>, u64>, Control>)->Stream<Child<'a, Worker<A>, u64>, D2>,
+    {
+        let plan = self.plan(worker.peers());
+        let mut input_handle = InputHandle::new();
+        let mut reference = ControlHandle::new();
+        let mut migrating = ControlHandle::new();
+        let mut probe = ProbeHandle::new();
+        let first_divergence: Rc<RefCell<Option<Divergence<u64, D2>>>> = Rc::new(RefCell::new(None));
+
+        let sink = Rc::clone(&first_divergence);
+        worker.dataflow::<u64, _, _>(|scope| {
+            let data = input_handle.to_stream(scope);
+            let expected = logic(&data, &reference.to_stream(scope));
+            let actual = logic(&data, &migrating.to_stream(scope));
+            divergences(&expected, &actual)
+                .inspect(move |divergence| {
+                    let mut first = sink.borrow_mut();
+                    if first.as_ref().map_or(true, |first| divergence.time < first.time) {
+                        *first = Some(divergence.clone());
+                    }
+                })
+                .probe_with(&mut probe);
+        });
+
+        let mut batches = plan.iter().peekable();
+        for round in 0..self.rounds {
+            if let Some(&&(_, ref batch)) = batches.peek().filter(|&&&(time, _)| time == round) {
+                migrating.send(batch.clone()).expect("generated batches are valid");
+                batches.next();
+            }
+            for record in input(round) {
+                input_handle.send(record);
+            }
+            input_handle.advance_to(round + 1);
+            reference.advance_to(round + 1);
+            migrating.advance_to(round + 1);
+            while probe.less_than(input_handle.time()) {
+                worker.step();
+            }
+        }
+        input_handle.close();
+        reference.close();
+        migrating.close();
+        while !probe.done() {
+            worker.step();
+        }
+
+        let divergence = first_divergence.borrow_mut().take();
+        match divergence {
+            Some(mut divergence) => {
+                divergence.controls = plan.into_iter().filter(|&(time, _)| time <= divergence.time).collect();
+                Err(divergence)
+            },
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Migratable top-k computation, implemented with Megaphone.
+//!
+//! The top-k operator works in two levels. The first level maintains the score of each key in
+//! migratable bin state and reports the best `k` candidates of each bin whenever the bin changes.
+//! The second level collects the latest candidates of all bins on a single worker and reports the
+//! global top `k` for each time. As the candidates are tracked per bin rather than per worker, the
+//! result does not depend on the placement of bins.
+use std::hash::Hash;
+
+use fnv::FnvHashMap as HashMap;
+
+use timely::ExchangeData;
+use timely::dataflow::{Stream, Scope};
+use timely::dataflow::channels::pact::Exchange;
+use timely::dataflow::operators::{FrontierNotificator, Operator};
+use timely::order::TotalOrder;
+
+use operator::StatefulOperator;
+use ::{calculate_hash, key_to_bin, Control, Key};
+
+/// Provide a top-k operator on streams of score updates.
+pub trait TopK<S, K, V>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq+Ord,
+    V: ExchangeData+Eq+Ord,
+{
+    /// Maintains a score per key and reports the `k` keys with the highest scores for each time at
+    /// which scores changed, ordered by descending score. Ties are broken by key.
+    ///
+    /// The input consists of score updates: `(key, Some(score))` replaces the score of `key`, and
+    /// `(key, None)` removes `key`.
+    ///
+    /// Only scores are migratable and spread across workers. The global top `k` is merged on
+    /// worker 0, which receives up to `k` candidates per changed bin and time, and the result is
+    /// only produced on worker 0.
+    fn stateful_top_k(&self, k: usize, control: &Stream<S, Control>) -> Stream<S, Vec<(K, V)>>;
+}
+
+impl<S, K, V> TopK<S, K, V> for Stream<S, (K, Option<V>)>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq+Ord,
+    V: ExchangeData+Eq+Ord,
+{
+    fn stateful_top_k(&self, k: usize, control: &Stream<S, Control>) -> Stream<S, Vec<(K, V)>> {
+        // Level one: maintain scores per bin, report candidates of changed bins
+        let candidates = self.stateful_unary(control, |(key, _score)| calculate_hash(key), "TopKCandidates", move |cap, data, bin, output| {
+            let scores: &mut HashMap<_, _> = bin.state();
+            let mut session_cap = cap.clone();
+            let mut changed = None;
+            for (time, (key, score)) in data.drain(..) {
+                if *session_cap.time() != time {
+                    if let Some(bin_id) = changed.take() {
+                        output.session(&session_cap).give((bin_id, top_k(scores.iter(), k)));
+                    }
+                    session_cap = cap.delayed(&time);
+                }
+                changed = Some(key_to_bin(Key(calculate_hash(&key))));
+                match score {
+                    Some(score) => { scores.insert(key, score); },
+                    None => { scores.remove(&key); },
+                }
+            }
+            if let Some(bin_id) = changed {
+                output.session(&session_cap).give((bin_id, top_k(scores.iter(), k)));
+            }
+        });
+
+        // Level two: merge the latest candidates of all bins on a single worker
+        candidates.unary_frontier(Exchange::new(|_| 0), "TopKMerge", move |_cap, _info| {
+            let mut notificator = FrontierNotificator::new();
+            let mut stash: HashMap<S::Timestamp, Vec<(usize, Vec<(K, V)>)>> = Default::default();
+            let mut bins: HashMap<usize, Vec<(K, V)>> = Default::default();
+            let mut data_buffer = Vec::new();
+            move |input, output| {
+                input.for_each(|time, data| {
+                    data.swap(&mut data_buffer);
+                    stash.entry(time.time().clone()).or_insert_with(Vec::new).extend(data_buffer.drain(..));
+                    notificator.notify_at(time.retain());
+                });
+                while let Some(time) = notificator.next(&[input.frontier()]) {
+                    if let Some(updates) = stash.remove(time.time()) {
+                        for (bin_id, candidates) in updates {
+                            bins.insert(bin_id, candidates);
+                        }
+                        let top = top_k(bins.values().flat_map(|candidates| candidates.iter().map(|&(ref key, ref score)| (key, score))), k);
+                        output.session(&time).give(top);
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Selects the `k` entries with the highest scores, ordered by descending score and key.
+fn top_k<'a, K: Ord+Clone+'a, V: Ord+Clone+'a, I: Iterator<Item=(&'a K, &'a V)>>(entries: I, k: usize) -> Vec<(K, V)> {
+    let mut top: Vec<(&K, &V)> = Vec::with_capacity(k + 1);
+    for (key, score) in entries {
+        let position = top.iter().position(|&(k2, s2)| (score, k2) > (s2, key)).unwrap_or(top.len());
+        if position < k {
+            top.insert(position, (key, score));
+            top.truncate(k);
+        }
+    }
+    top.into_iter().map(|(key, score)| (key.clone(), score.clone())).collect()
+}
@@ -0,0 +1,308 @@
+//! Migratable windowed aggregation on streams of `(key, value)` pairs, implemented with Megaphone.
+//!
+//! Records are assigned to windows by their event time, which is obtained from a user-supplied
+//! timestamp extractor. Event times are plain `u64`s, for example milliseconds. A window ending at
+//! event time `end` fires once the dataflow frontier passes `to_time(end)`, where `to_time` is a
+//! user-supplied conversion to dataflow timestamps. Records arriving at a dataflow time later than
+//! the firing time of their window are dropped.
+//!
+//! The state of open windows is kept per key in the bins and migrates with them.
+use std::hash::Hash;
+use std::ops::AddAssign;
+
+use fnv::FnvHashMap as HashMap;
+
+use timely::{Data, ExchangeData};
+use timely::dataflow::{Stream, Scope};
+use timely::order::{PartialOrder, TotalOrder};
+
+use notificator::TimerKeys;
+use operator::StatefulOperator;
+use ::{calculate_hash, Control};
+
+/// Aggregation logic applied to the values of a window.
+pub trait Aggregate<V>: 'static {
+    /// The state maintained for each open window.
+    type State: ExchangeData+Default;
+    /// The result reported when a window fires.
+    type Output: Data;
+    /// Add a value to the state of a window.
+    fn add(&self, state: &mut Self::State, value: V);
+    /// Merge the state of another window into `state`. Used when session windows merge.
+    fn merge(&self, state: &mut Self::State, other: Self::State);
+    /// Compute the result of a window from its state.
+    fn finish(&self, state: Self::State) -> Self::Output;
+}
+
+/// Counts the values in a window.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Count;
+
+impl<V> Aggregate<V> for Count {
+    type State = u64;
+    type Output = u64;
+    fn add(&self, state: &mut u64, _value: V) {
+        *state += 1;
+    }
+    fn merge(&self, state: &mut u64, other: u64) {
+        *state += other;
+    }
+    fn finish(&self, state: u64) -> u64 {
+        state
+    }
+}
+
+/// Sums the values in a window.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sum;
+
+impl<V: ExchangeData+Default+AddAssign> Aggregate<V> for Sum {
+    type State = V;
+    type Output = V;
+    fn add(&self, state: &mut V, value: V) {
+        *state += value;
+    }
+    fn merge(&self, state: &mut V, other: V) {
+        *state += other;
+    }
+    fn finish(&self, state: V) -> V {
+        state
+    }
+}
+
+/// Provides windowed aggregations on streams of `(key, value)` pairs that can be migrated.
+///
+/// Each window reports `(key, (start, end), result)`, where `[start, end)` is the window's range of
+/// event times, at the dataflow time `to_time(end)`.
+pub trait Window<S, K, V>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Eq,
+{
+    /// Aggregates values in non-overlapping windows of `size` event time units. Panics if `size` is
+    /// zero.
+    fn tumbling_window<A, E, C>(&self, size: u64, extract: E, to_time: C, aggregate: A, control: &Stream<S, Control>) -> Stream<S, (K, (u64, u64), A::Output)>
+        where
+            A: Aggregate<V>,
+            E: Fn(&V)->u64+'static,
+            C: Fn(u64)->S::Timestamp+'static;
+
+    /// Aggregates values in windows of `size` event time units, starting every `slide` units.
+    /// Panics if `size` or `slide` is zero.
+    fn sliding_window<A, E, C>(&self, size: u64, slide: u64, extract: E, to_time: C, aggregate: A, control: &Stream<S, Control>) -> Stream<S, (K, (u64, u64), A::Output)>
+        where
+            A: Aggregate<V>,
+            E: Fn(&V)->u64+'static,
+            C: Fn(u64)->S::Timestamp+'static;
+
+    /// Aggregates values per key in sessions, i.e. windows of activity separated by at least `gap`
+    /// event time units without values.
+    fn session_window<A, E, C>(&self, gap: u64, extract: E, to_time: C, aggregate: A, control: &Stream<S, Control>) -> Stream<S, (K, (u64, u64), A::Output)>
+        where
+            A: Aggregate<V>,
+            E: Fn(&V)->u64+'static,
+            C: Fn(u64)->S::Timestamp+'static;
+}
+
+impl<S, K, V> Window<S, K, V> for Stream<S, (K, V)>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Eq,
+{
+    fn tumbling_window<A, E, C>(&self, size: u64, extract: E, to_time: C, aggregate: A, control: &Stream<S, Control>) -> Stream<S, (K, (u64, u64), A::Output)>
+        where
+            A: Aggregate<V>,
+            E: Fn(&V)->u64+'static,
+            C: Fn(u64)->S::Timestamp+'static,
+    {
+        assert!(size > 0, "size must be positive");
+        window_core(self, Windows::Sliding { size, slide: size }, extract, to_time, aggregate, control, "TumblingWindow")
+    }
+
+    fn sliding_window<A, E, C>(&self, size: u64, slide: u64, extract: E, to_time: C, aggregate: A, control: &Stream<S, Control>) -> Stream<S, (K, (u64, u64), A::Output)>
+        where
+            A: Aggregate<V>,
+            E: Fn(&V)->u64+'static,
+            C: Fn(u64)->S::Timestamp+'static,
+    {
+        assert!(size > 0, "size must be positive");
+        assert!(slide > 0, "slide must be positive");
+        window_core(self, Windows::Sliding { size, slide }, extract, to_time, aggregate, control, "SlidingWindow")
+    }
+
+    fn session_window<A, E, C>(&self, gap: u64, extract: E, to_time: C, aggregate: A, control: &Stream<S, Control>) -> Stream<S, (K, (u64, u64), A::Output)>
+        where
+            A: Aggregate<V>,
+            E: Fn(&V)->u64+'static,
+            C: Fn(u64)->S::Timestamp+'static,
+    {
+        window_core(self, Windows::Session { gap }, extract, to_time, aggregate, control, "SessionWindow")
+    }
+}
+
+/// Window assignment strategies. Tumbling windows are sliding windows with `slide == size`.
+#[derive(Clone, Copy, Debug)]
+enum Windows {
+    Sliding { size: u64, slide: u64 },
+    Session { gap: u64 },
+}
+
+/// Notifications of the window operator.
+#[derive(Abomonation, Clone, Debug, Eq, PartialEq)]
+enum WindowEvent<K, V> {
+    /// A record with its event time.
+    Data(K, u64, V),
+    /// Fire the window `[start, end)` of a key, if it still exists.
+    Fire(K, u64, u64),
+}
+
+/// Open windows per key: start, end, timer key and aggregation state.
+type OpenWindows<A> = Vec<(u64, u64, u64, A)>;
+
+/// State of a bin: the open windows of each key and the timer keys they use.
+#[derive(Clone)]
+struct WindowState<K: Hash+Eq, A> {
+    windows: HashMap<K, OpenWindows<A>>,
+    timer_keys: TimerKeys,
+}
+
+impl<K: Hash+Eq, A> Default for WindowState<K, A> {
+    fn default() -> Self {
+        WindowState { windows: Default::default(), timer_keys: Default::default() }
+    }
+}
+
+impl<K: Hash+Eq, A> IntoIterator for WindowState<K, A> {
+    type Item = (K, OpenWindows<A>);
+    type IntoIter = ::std::collections::hash_map::IntoIter<K, OpenWindows<A>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.windows.into_iter()
+    }
+}
+
+impl<K: Hash+Eq, A> Extend<(K, OpenWindows<A>)> for WindowState<K, A> {
+    fn extend<I: IntoIterator<Item=(K, OpenWindows<A>)>>(&mut self, iter: I) {
+        for (key, open) in iter {
+            for window in &open {
+                self.timer_keys.insert(window.2);
+            }
+            self.windows.insert(key, open);
+        }
+    }
+}
+
+/// A change to the timers of a bin, applied in order once all events are processed.
+enum TimerUpdate<T, E> {
+    Schedule(u64, T, E),
+    Cancel(u64),
+}
+
+fn window_core<S, K, V, A, E, C>(stream: &Stream<S, (K, V)>, windows: Windows, extract: E, to_time: C, aggregate: A, control: &Stream<S, Control>, name: &str) -> Stream<S, (K, (u64, u64), A::Output)>
+where
+    S: Scope,
+    S::Timestamp: TotalOrder,
+    K: ExchangeData+Hash+Eq,
+    V: ExchangeData+Eq,
+    A: Aggregate<V>,
+    E: Fn(&V)->u64+'static,
+    C: Fn(u64)->S::Timestamp+'static,
+{
+    let mut data_buffer = vec![];
+    let mut timers = vec![];
+
+    stream.stateful_unary_input(control, |d| calculate_hash(&d.0), name, move |state, cap, time, data, _output| {
+        data.swap(&mut data_buffer);
+        for (_worker, key_id, (key, value)) in data_buffer.drain(..) {
+            let event_time = extract(&value);
+            state.get(key_id).notificator().notify_at_data(&cap, time.clone(), WindowEvent::Data(key, event_time, value));
+        }
+    }, move |cap, data, bin, output| {
+        // Records are processed before windows firing at the same time
+        data.sort_by(|(t1, e1), (t2, e2)| {
+            let fire = |e: &WindowEvent<_, _>| if let WindowEvent::Fire(..) = *e { 1 } else { 0 };
+            t1.cmp(t2).then_with(|| fire(e1).cmp(&fire(e2)))
+        });
+
+        for (time, event) in data.drain(..) {
+            let state: &mut WindowState<_, _> = bin.state();
+            let WindowState { windows: ref mut open_windows, ref mut timer_keys } = *state;
+            match event {
+                WindowEvent::Data(key, event_time, value) => match windows {
+                    Windows::Sliding { size, slide } => {
+                        let mut start = event_time - event_time % slide;
+                        while start + size > event_time {
+                            let end = start + size;
+                            let fire_time = to_time(end);
+                            if !fire_time.less_than(&time) {
+                                let open = open_windows.entry(key.clone()).or_insert_with(Vec::new);
+                                if let Some(window) = open.iter_mut().find(|w| w.0 == start) {
+                                    aggregate.add(&mut window.3, value.clone());
+                                } else {
+                                    let mut window = Default::default();
+                                    aggregate.add(&mut window, value.clone());
+                                    let timer_key = timer_keys.allocate(calculate_hash(&(&key, start)));
+                                    open.push((start, end, timer_key, window));
+                                    timers.push(TimerUpdate::Schedule(timer_key, fire_time, WindowEvent::Fire(key.clone(), start, end)));
+                                }
+                            }
+                            if start < slide { break; }
+                            start -= slide;
+                        }
+                    },
+                    Windows::Session { gap } => {
+                        let (mut start, mut end) = (event_time, event_time + gap);
+                        if !to_time(end).less_than(&time) {
+                            let mut window = Default::default();
+                            aggregate.add(&mut window, value);
+                            let open = open_windows.entry(key.clone()).or_insert_with(Vec::new);
+                            let mut index = 0;
+                            while index < open.len() {
+                                if open[index].0 < end && start < open[index].1 {
+                                    // Merged sessions give up their timers
+                                    let (s, e, timer_key, other) = open.swap_remove(index);
+                                    start = ::std::cmp::min(start, s);
+                                    end = ::std::cmp::max(end, e);
+                                    aggregate.merge(&mut window, other);
+                                    timer_keys.release(timer_key);
+                                    timers.push(TimerUpdate::Cancel(timer_key));
+                                } else {
+                                    index += 1;
+                                }
+                            }
+                            let timer_key = timer_keys.allocate(calculate_hash(&(&key, start)));
+                            open.push((start, end, timer_key, window));
+                            timers.push(TimerUpdate::Schedule(timer_key, to_time(end), WindowEvent::Fire(key, start, end)));
+                        }
+                    },
+                },
+                WindowEvent::Fire(key, start, end) => {
+                    let mut result = None;
+                    if let Some(open) = open_windows.get_mut(&key) {
+                        if let Some(position) = open.iter().position(|w| w.0 == start && w.1 == end) {
+                            let (_start, _end, timer_key, window) = open.swap_remove(position);
+                            timer_keys.release(timer_key);
+                            result = Some(window);
+                        }
+                    }
+                    if let Some(window) = result {
+                        if open_windows.get(&key).map_or(false, |open| open.is_empty()) {
+                            open_windows.remove(&key);
+                        }
+                        output.session(&cap.delayed(&time)).give((key, (start, end), aggregate.finish(window)));
+                    }
+                },
+            }
+        }
+
+        for update in timers.drain(..) {
+            match update {
+                TimerUpdate::Schedule(timer_key, time, event) => bin.notificator().notify_at_key(&cap, timer_key, time, event),
+                TimerUpdate::Cancel(timer_key) => { bin.notificator().cancel(timer_key); },
+            }
+        }
+    })
+}
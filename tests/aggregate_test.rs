@@ -0,0 +1,66 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use timely::dataflow::*;
+use timely::dataflow::operators::{Input, Probe, Inspect};
+
+use timely::Configuration;
+
+use dynamic_scaling_mechanism::{BIN_SHIFT, ControlInst, Control};
+use dynamic_scaling_mechanism::aggregate::StatefulAggregate;
+
+#[test]
+fn aggregate_across_migration() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index() as u64;
+        let results = results2.clone();
+        let mut input = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control = scope.input_from(&mut control_input);
+            scope.input_from(&mut input)
+                .stateful_aggregate(|sum: &mut u64, value| *sum += value, &control)
+                .inspect_batch(move |time, data| results.lock().unwrap().extend(data.iter().map(|x| (*time, *x))))
+                .probe_with(&mut probe);
+        });
+
+        // All bins move to worker 1 at time 5
+        control_input.advance_to(5);
+        control_input.send(Control::new(0, 1, ControlInst::Map(vec![1; 1 << BIN_SHIFT])));
+        control_input.advance_to(10);
+        for round in 0..10u64 {
+            for key in 0..(round % 4 + 1) {
+                input.send((key, round + index));
+                input.send((key, 1));
+            }
+            input.advance_to(round + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    // Both workers send `(key, round + index)` and `(key, 1)`, keys are reported with their sums
+    // at each time they receive values
+    let mut sums = HashMap::new();
+    let mut expected = Vec::new();
+    for round in 0..10u64 {
+        for key in 0..(round % 4 + 1) {
+            let sum = sums.entry(key).or_insert(0);
+            *sum += 2 * round + 1 + 2;
+            expected.push((round, (key, *sum)));
+        }
+    }
+    expected.sort();
+    assert_eq!(expected, results);
+}
@@ -0,0 +1,229 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use timely::dataflow::*;
+use timely::dataflow::operators::{Input, Probe, Inspect};
+
+use timely::Configuration;
+
+use dynamic_scaling_mechanism::{Bin, BinId, BIN_SHIFT, ControlInst, Control, StatefulConfig};
+use dynamic_scaling_mechanism::control::{parse_instructions, ControlEndpoint, SplitControl};
+use dynamic_scaling_mechanism::operator::{StatefulBuilder, StatefulOperator};
+
+#[test]
+fn invalid_move_keeps_map() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index();
+        let results = results2.clone();
+        let mut input = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+        let config = StatefulConfig::new();
+
+        worker.dataflow(|scope| {
+            let control = scope.input_from(&mut control_input);
+            let input = scope.input_from(&mut input);
+            StatefulBuilder::new("Owner", &control)
+                .config(config.clone())
+                .unary(&input, |key: &u64| *key << (64 - BIN_SHIFT), move |cap, data, _bin: &mut Bin<_, Vec<()>, _>, output| {
+                    let mut session = output.session(cap);
+                    for (time, key) in data.drain(..) {
+                        session.give((time, key, index));
+                    }
+                })
+                .inspect(move |x| results.lock().unwrap().push(*x))
+                .probe_with(&mut probe);
+        });
+
+        // Worker 5 does not exist, the batch is rejected
+        control_input.send(Control::new(0, 1, ControlInst::Move(BinId::new(0), 5)));
+        control_input.advance_to(3);
+        // The batch claims two instructions but only has one, it is rejected
+        control_input.send(Control::new(1, 2, ControlInst::Move(BinId::new(1), 0)));
+        control_input.advance_to(5);
+        // Later configurations still apply
+        control_input.send(Control::new(2, 1, ControlInst::Move(BinId::new(1), 0)));
+        control_input.advance_to(10);
+        for round in 0..10u64 {
+            if index == 0 {
+                input.send(0);
+                input.send(1);
+            }
+            input.advance_to(round + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+        assert_eq!(2, config.rejected_batches().len());
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    let mut expected = Vec::new();
+    for round in 0..10u64 {
+        expected.push((round, 0, 0));
+        expected.push((round, 1, if round < 5 { 1 } else { 0 }));
+    }
+    assert_eq!(expected, results);
+}
+
+#[test]
+fn sequences_out_of_time_order() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index();
+        let results = results2.clone();
+        let mut input = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control = scope.input_from(&mut control_input);
+            let input = scope.input_from(&mut input);
+            input
+                .stateful_unary(&control, |key: &u64| *key << (64 - BIN_SHIFT), "Owner", move |cap, data, _bin: &mut Bin<_, Vec<()>, _>, output| {
+                    let mut session = output.session(cap);
+                    for (time, key) in data.drain(..) {
+                        session.give((time, key, index));
+                    }
+                })
+                .inspect(move |x| results.lock().unwrap().push(*x))
+                .probe_with(&mut probe);
+        });
+
+        // The later sequence number applies first, configurations take effect in time order
+        control_input.advance_to(2);
+        control_input.send(Control::new(1, 1, ControlInst::Move(BinId::new(1), 0)));
+        control_input.advance_to(5);
+        control_input.send(Control::new(0, 1, ControlInst::Move(BinId::new(1), 1)));
+        control_input.advance_to(10);
+        for round in 0..10u64 {
+            if index == 0 {
+                input.send(1);
+            }
+            input.advance_to(round + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    let expected = (0..10u64).map(|round| (round, 1, if round >= 2 && round < 5 { 0 } else { 1 })).collect::<Vec<_>>();
+    assert_eq!(expected, results);
+}
+
+#[test]
+fn split_control_drops_invalid_instructions() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index();
+        let results = results2.clone();
+        let mut input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            // Records of 1000 and above move all bins to worker `record - 1000`
+            let (input, control) = scope.input_from(&mut input)
+                .split_control(|x: &u64| if *x >= 1000 { Some(ControlInst::Map(vec![(*x - 1000) as usize; 1 << BIN_SHIFT])) } else { None });
+            input
+                .stateful_unary(&control, |key: &u64| *key << (64 - BIN_SHIFT), "Owner", move |cap, data, _bin: &mut Bin<_, Vec<()>, _>, output| {
+                    let mut session = output.session(cap);
+                    for (time, key) in data.drain(..) {
+                        session.give((time, key, index));
+                    }
+                })
+                .inspect(move |x| results.lock().unwrap().push(*x))
+                .probe_with(&mut probe);
+        });
+
+        for round in 0..10u64 {
+            if index == 0 {
+                input.send(1);
+                if round == 3 {
+                    // Worker 5 does not exist, the instruction is dropped
+                    input.send(1005);
+                }
+                if round == 6 {
+                    input.send(1000);
+                }
+            }
+            input.advance_to(round + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    let expected = (0..10u64).map(|round| (round, 1, if round < 6 { 1 } else { 0 })).collect::<Vec<_>>();
+    assert_eq!(expected, results);
+}
+
+#[test]
+fn parse_map() {
+    assert_eq!(Ok(Some(vec![ControlInst::Map(vec![0, 1, 1, 0])])), parse_instructions("M 0 1 1 0"));
+    assert!(parse_instructions("M").is_err());
+}
+
+#[test]
+fn parse_move() {
+    assert_eq!(Ok(Some(vec![ControlInst::Move(BinId::new(0), 1), ControlInst::Move(BinId::new(3), 0)])), parse_instructions("D 0 1  3 0"));
+    assert!(parse_instructions("D").is_err());
+    assert!(parse_instructions("D 0 1 3").is_err());
+}
+
+#[test]
+fn parse_abort() {
+    assert_eq!(Ok(Some(vec![ControlInst::Abort(2), ControlInst::Abort(5)])), parse_instructions("A 2 5"));
+    assert!(parse_instructions("A").is_err());
+}
+
+#[test]
+fn parse_ignores_empty_lines_and_comments() {
+    assert_eq!(Ok(None), parse_instructions(""));
+    assert_eq!(Ok(None), parse_instructions("   "));
+    assert_eq!(Ok(None), parse_instructions("# M 0 1"));
+    assert_eq!(Ok(None), parse_instructions("#"));
+}
+
+#[test]
+fn parse_rejects_malformed_lines() {
+    assert!(parse_instructions("X 0 1").is_err());
+    assert!(parse_instructions("m 0 1").is_err());
+    assert!(parse_instructions("M 0 one").is_err());
+    assert!(parse_instructions("M 0 -1").is_err());
+    assert!(parse_instructions("A 1.5").is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_endpoint_replaces_stale_socket() {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    let path = ::std::env::temp_dir().join(format!("megaphone-control-test-{}.sock", ::std::process::id()));
+    let _ = ::std::fs::remove_file(&path);
+    let endpoint = ControlEndpoint::Unix(path.clone());
+    // The first listener leaves its socket behind
+    endpoint.listen().unwrap();
+    let receiver = endpoint.listen().unwrap();
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream.write_all(b"D 1 0\n").unwrap();
+    drop(stream);
+    assert_eq!(vec![ControlInst::Move(BinId::new(1), 0)], receiver.recv_timeout(Duration::from_secs(10)).unwrap());
+    ::std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,53 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use std::sync::{Arc, Mutex};
+
+use timely::dataflow::*;
+use timely::dataflow::operators::{Input, Probe, Inspect};
+
+use timely::Configuration;
+
+use dynamic_scaling_mechanism::Control;
+use dynamic_scaling_mechanism::distinct::StatefulDistinct;
+
+#[test]
+fn distinct_ttl_expiry_and_readmission() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index();
+        let results = results2.clone();
+        let mut input = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control: Stream<_, Control> = scope.input_from(&mut control_input);
+            scope.input_from(&mut input)
+                .stateful_distinct(Some(3), &control)
+                .inspect_batch(move |time, data| results.lock().unwrap().extend(data.iter().map(|x| (*time, *x))))
+                .probe_with(&mut probe);
+        });
+
+        control_input.advance_to(10);
+        // 7 is seen until 2 and expires at 5, 8 is only seen at 0 and expires at 3
+        let rounds: Vec<Vec<u64>> = vec![vec![7, 8, 7], vec![7], vec![7], vec![8], vec![], vec![], vec![7, 7]];
+        for (round, records) in rounds.into_iter().enumerate() {
+            if index == 0 {
+                for record in records {
+                    input.send(record);
+                }
+            }
+            input.advance_to(round as u64 + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    assert_eq!(vec![(0, 7), (0, 8), (3, 8), (6, 7)], results);
+}
@@ -168,8 +168,7 @@ fn adjacent_configuration() {
 
 
 #[test]
-#[should_panic]
-fn error_seq_configuration() {
+fn inconsistent_sequence_rejected() {
     timely::execute(Configuration::Process(2), |worker| {
 
         // these results happen to be right, but aren't guaranteed.
@@ -204,6 +203,7 @@ fn error_seq_configuration() {
         });
 
         control_input.advance_to(3);
+        // Two batches with distinct sequence numbers at the same time are rejected
         control_input.send(Control::new(10,  1, ControlInst::Map(vec![0; 1 << BIN_SHIFT])));
 //        worker.step();
 //        control_input.advance_to(4);
@@ -0,0 +1,168 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use std::sync::{Arc, Mutex};
+
+use timely::dataflow::*;
+use timely::dataflow::operators::{Input, Probe, Inspect};
+
+use timely::Configuration;
+
+use dynamic_scaling_mechanism::{BIN_SHIFT, ControlInst, Control};
+use dynamic_scaling_mechanism::join::{Join, Retention};
+
+#[test]
+fn inner_join_migration() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index();
+        let results = results2.clone();
+        let mut left = InputHandle::new();
+        let mut right = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control = scope.input_from(&mut control_input);
+            let left = scope.input_from(&mut left);
+            let right = scope.input_from(&mut right);
+            left
+                .join(&right, Retention::Forever, &control)
+                .inspect(move |x| results.lock().unwrap().push(*x))
+                .probe_with(&mut probe);
+        });
+
+        control_input.send(Control::new(0,  1, ControlInst::Map(vec![0; 1 << BIN_SHIFT])));
+        control_input.advance_to(3);
+        control_input.send(Control::new(1,  1, ControlInst::Map(vec![1; 1 << BIN_SHIFT])));
+        control_input.advance_to(10);
+        for round in 0..6u64 {
+            if index == 0 {
+                left.send((round % 3, round));
+                right.send((round % 3, round * 10));
+            }
+            left.advance_to(round + 1);
+            right.advance_to(round + 1);
+            while probe.less_than(left.time()) {
+                worker.step();
+            }
+        }
+
+    }).unwrap();
+
+    let mut expected = Vec::new();
+    for l in 0..6u64 {
+        for r in 0..6u64 {
+            if l % 3 == r % 3 {
+                expected.push((l % 3, l, r * 10));
+            }
+        }
+    }
+    expected.sort();
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    assert_eq!(expected, results);
+}
+
+#[test]
+fn left_join_within() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index();
+        let results = results2.clone();
+        let mut left = InputHandle::new();
+        let mut right = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control = scope.input_from(&mut control_input);
+            let left = scope.input_from(&mut left);
+            let right = scope.input_from(&mut right);
+            left
+                .left_join(&right, Retention::Within(2), &control)
+                .inspect(move |x| results.lock().unwrap().push(*x))
+                .probe_with(&mut probe);
+        });
+
+        control_input.advance_to(10);
+        for round in 0..10u64 {
+            if index == 0 {
+                if round == 0 || round == 5 {
+                    left.send((0u64, round));
+                }
+                if round == 1 || round == 8 {
+                    right.send((0u64, round));
+                }
+            }
+            left.advance_to(round + 1);
+            right.advance_to(round + 1);
+            while probe.less_than(left.time()) {
+                worker.step();
+            }
+        }
+
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    assert_eq!(vec![(0, 0, Some(1)), (0, 5, None)], results);
+}
+
+#[test]
+fn outer_join_within_boundary() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index();
+        let results = results2.clone();
+        let mut left = InputHandle::new();
+        let mut right = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control = scope.input_from(&mut control_input);
+            let left = scope.input_from(&mut left);
+            let right = scope.input_from(&mut right);
+            left
+                .outer_join(&right, Retention::Within(2), &control)
+                .inspect_batch(move |time, data| results.lock().unwrap().extend(data.iter().map(|x| (*time, *x))))
+                .probe_with(&mut probe);
+        });
+
+        control_input.advance_to(10);
+        for round in 0..10u64 {
+            if index == 0 {
+                // Key 0 is exactly `summary` apart and does not match
+                if round == 0 { left.send((0u64, round)); left.send((1, round)); }
+                if round == 2 { right.send((0u64, round)); }
+                // Key 1 matches at the time of the right record
+                if round == 1 { right.send((1, round)); }
+                // Key 2 matches at the time of the left record
+                if round == 3 { right.send((2, round)); }
+                if round == 4 { left.send((2, round)); }
+            }
+            left.advance_to(round + 1);
+            right.advance_to(round + 1);
+            while probe.less_than(left.time()) {
+                worker.step();
+            }
+        }
+
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    assert_eq!(vec![
+        (1, (1, Some(0), Some(1))),
+        (2, (0, Some(0), None)),
+        (4, (0, None, Some(2))),
+        (4, (2, Some(4), Some(3))),
+    ], results);
+}
@@ -0,0 +1,61 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use timely::Configuration;
+use timely::dataflow::{InputHandle, ProbeHandle};
+use timely::dataflow::operators::{Input, Map, Probe};
+
+use dynamic_scaling_mechanism::{BIN_SHIFT, ControlInst};
+use dynamic_scaling_mechanism::control::ControlHandle;
+use dynamic_scaling_mechanism::latency::{LatencyRecorder, MeasureLatency};
+use dynamic_scaling_mechanism::state_machine::BinnedStateMachine;
+
+#[test]
+fn latency_of_migrating_word_count() {
+    const ROUNDS: u64 = 10;
+    timely::execute(Configuration::Process(2), |worker| {
+        let index = worker.index() as u64;
+        let peers = worker.peers();
+        let recorder = LatencyRecorder::new();
+        let mut input = InputHandle::new();
+        let mut control = ControlHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control = control.to_stream(scope);
+            scope.input_from(&mut input)
+                .latency_start(&recorder)
+                .map(|word: u64| (word, 1))
+                .stateful_state_machine(|word: &u64, count: u64, total: &mut u64| {
+                    *total += count;
+                    (false, Some((*word, *total)))
+                }, |word| *word, &control)
+                .latency_end(&recorder)
+                .probe_with(&mut probe);
+        });
+
+        for round in 0..ROUNDS {
+            if round == ROUNDS / 2 {
+                control.send(vec![ControlInst::Map(vec![peers - 1; 1 << BIN_SHIFT])]).unwrap();
+            }
+            for word in 0..100 {
+                input.send((word * 7 + index + round) % 50);
+            }
+            input.advance_to(round + 1);
+            control.advance_to(round + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+        input.close();
+        control.close();
+        while worker.step() {}
+
+        // Every worker sent records in every round, all of which completed
+        assert_eq!(0, recorder.pending());
+        let epochs = recorder.epochs();
+        assert_eq!((0..ROUNDS).collect::<Vec<_>>(), epochs.iter().map(|&(time, _)| time).collect::<Vec<_>>());
+        let histogram = recorder.histogram();
+        assert_eq!(epochs.len() as u64, histogram.ccdf().map(|(_value, _prob, count)| count).sum::<u64>());
+    }).unwrap();
+}
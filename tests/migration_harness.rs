@@ -0,0 +1,71 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use timely::Configuration;
+use timely::dataflow::operators::Map;
+
+use dynamic_scaling_mechanism::{Bin, BIN_SHIFT};
+use dynamic_scaling_mechanism::operator::StatefulOperator;
+use dynamic_scaling_mechanism::state_machine::BinnedStateMachine;
+use dynamic_scaling_mechanism::testing::MigrationHarness;
+
+#[test]
+fn state_machine_is_migration_safe() {
+    timely::execute(Configuration::Process(2), |worker| {
+        let index = worker.index() as u64;
+        let result = MigrationHarness::new(0x5eed)
+            .rounds(50)
+            .migration_probability(0.3)
+            .run(worker, |round| (0..10).map(|x| (x + index + round) % 7).collect(), |input, control| {
+                input
+                    .map(|x| (x, 1))
+                    .stateful_state_machine(|key: &u64, val: u64, agg: &mut u64| {
+                        *agg += val;
+                        (false, Some((*key, *agg)))
+                    }, |key| *key << (64 - BIN_SHIFT), control)
+            });
+        if let Err(divergence) = result {
+            panic!("{}", divergence);
+        }
+    }).unwrap();
+}
+
+#[test]
+fn worker_local_state_diverges() {
+    timely::execute(Configuration::Process(2), |worker| {
+        let index = worker.index() as u64;
+        let result = MigrationHarness::new(0x5eed)
+            .rounds(50)
+            .migration_probability(0.3)
+            .run(worker, |round| (0..10).map(|x| (x + index + round) % 7).collect(), |input, control| {
+                // Counts are kept per worker rather than in the bins, so they do not migrate
+                let counts = Rc::new(RefCell::new(HashMap::new()));
+                input.stateful_unary(control, |key: &u64| *key << (64 - BIN_SHIFT), "LocalCount", move |cap, data, _bin: &mut Bin<_, Vec<()>, _>, output| {
+                    let mut counts = counts.borrow_mut();
+                    let mut session = output.session(cap);
+                    for (_time, key) in data.drain(..) {
+                        let count = counts.entry(key).or_insert(0u64);
+                        *count += 1;
+                        session.give((key, *count));
+                    }
+                })
+            });
+        if index == 0 {
+            match result {
+                Ok(()) => panic!("Migrating worker-local state did not diverge"),
+                Err(divergence) => assert!(!divergence.controls.is_empty(), "{}", divergence),
+            }
+        }
+    }).unwrap();
+}
+
+#[test]
+fn plans_are_deterministic() {
+    let harness = MigrationHarness::new(42).rounds(20).migration_probability(0.5);
+    assert_eq!(format!("{:?}", harness.plan(4)), format!("{:?}", harness.plan(4)));
+    assert!(harness.plan(4).iter().all(|&(round, ref batch)| round < 20 && !batch.is_empty()));
+}
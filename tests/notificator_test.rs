@@ -0,0 +1,102 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use std::sync::{Arc, Mutex};
+
+use timely::dataflow::*;
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Input, Inspect, Operator, Probe};
+
+use timely::Configuration;
+
+use dynamic_scaling_mechanism::notificator::TotalOrderFrontierNotificator;
+
+#[test]
+fn cancel_releases_capability() {
+    timely::execute(Configuration::Thread, |worker| {
+        let mut input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            scope.input_from(&mut input)
+                .unary_frontier::<(), _, _, _>(Pipeline, "Cancel", |_cap, _info| {
+                    let mut notificator = TotalOrderFrontierNotificator::new();
+                    let mut buffer = Vec::new();
+                    move |input, _output| {
+                        input.for_each(|cap, data| {
+                            data.swap(&mut buffer);
+                            for x in buffer.drain(..) {
+                                if x {
+                                    notificator.notify_at_key(&cap.retain(), 0, 1_000, ());
+                                    assert!(notificator.capability().is_some());
+                                } else {
+                                    assert!(notificator.cancel(0));
+                                    assert!(notificator.capability().is_none());
+                                }
+                            }
+                        });
+                        notificator.for_each_data(&[input.frontier()], |_cap, _time, _data, _| {
+                            panic!("cancelled timer delivered");
+                        });
+                    }
+                })
+                .probe_with(&mut probe);
+        });
+
+        input.send(true);
+        input.advance_to(1);
+        input.send(false);
+        input.advance_to(5);
+        // The output frontier advances although the input remains open
+        while probe.less_than(input.time()) {
+            worker.step();
+        }
+    }).unwrap();
+}
+
+#[test]
+fn superseded_timers_deliver_once() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Thread, move |worker| {
+        let results = results2.clone();
+        let mut input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            scope.input_from(&mut input)
+                .unary_frontier(Pipeline, "Supersede", |_cap, _info| {
+                    let mut notificator = TotalOrderFrontierNotificator::new();
+                    let mut buffer = Vec::new();
+                    move |input, output| {
+                        input.for_each(|cap, data| {
+                            data.swap(&mut buffer);
+                            for x in buffer.drain(..) {
+                                // Each record extends the deadline of its key
+                                let time = *cap.time() + 10;
+                                notificator.notify_at_key(&cap.retain(), x % 2, time, x);
+                            }
+                        });
+                        notificator.for_each_data(&[input.frontier()], |cap, time, data, _| {
+                            output.session(cap).give((time, data));
+                        });
+                    }
+                })
+                .inspect(move |x| results.lock().unwrap().push(*x))
+                .probe_with(&mut probe);
+        });
+
+        for round in 0..100u64 {
+            input.send(round);
+            input.send(round + 2);
+            input.advance_to(round + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    assert_eq!(vec![(108, 100), (109, 101)], results);
+}
@@ -0,0 +1,79 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use std::sync::{Arc, Mutex};
+
+use timely::dataflow::*;
+use timely::dataflow::operators::{Input, Probe, Inspect};
+
+use timely::Configuration;
+
+use dynamic_scaling_mechanism::{Bin, BIN_SHIFT, ControlInst, Control};
+use dynamic_scaling_mechanism::operator::StatefulOperator;
+
+#[test]
+fn unary_outputs_across_migration() {
+    let counts = Arc::new(Mutex::new(Vec::new()));
+    let owners = Arc::new(Mutex::new(Vec::new()));
+    let (counts2, owners2) = (counts.clone(), owners.clone());
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index();
+        let (counts, owners) = (counts2.clone(), owners2.clone());
+        let mut input = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control = scope.input_from(&mut control_input);
+            let input = scope.input_from(&mut input);
+            // Counts records per key on the first output and reports the owning worker on the second
+            let outputs = input
+                .stateful_unary_outputs(&control, |key: &u64| *key << (64 - BIN_SHIFT), "Outputs", 2, move |cap, data, bin: &mut Bin<_, Vec<u64>, _>, output| {
+                    let mut session = output.session(cap);
+                    for (time, key) in data.drain(..) {
+                        bin.state().push(key);
+                        session.give((0, (time, key, bin.state().len())));
+                        session.give((1, (time, key, index)));
+                    }
+                });
+            let (count_stream, owner_stream) = (&outputs[0], &outputs[1]);
+            count_stream
+                .inspect(move |x| counts.lock().unwrap().push(*x))
+                .probe_with(&mut probe);
+            owner_stream
+                .inspect(move |x| owners.lock().unwrap().push(*x))
+                .probe_with(&mut probe);
+        });
+
+        // All bins move to worker 0 at time 5
+        control_input.advance_to(5);
+        control_input.send(Control::new(0, 1, ControlInst::Map(vec![0; 1 << BIN_SHIFT])));
+        control_input.advance_to(10);
+        for round in 0..10u64 {
+            if index == 0 {
+                input.send(0);
+                input.send(1);
+            }
+            input.advance_to(round + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+    }).unwrap();
+
+    let mut counts = counts.lock().unwrap().clone();
+    counts.sort();
+    let mut owners = owners.lock().unwrap().clone();
+    owners.sort();
+    let mut expected_counts = Vec::new();
+    let mut expected_owners = Vec::new();
+    for round in 0..10u64 {
+        for key in 0..2u64 {
+            expected_counts.push((round, key, round as usize + 1));
+            expected_owners.push((round, key, if round < 5 { key as usize } else { 0 }));
+        }
+    }
+    assert_eq!(expected_counts, counts);
+    assert_eq!(expected_owners, owners);
+}
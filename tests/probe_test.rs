@@ -0,0 +1,68 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use timely::dataflow::*;
+use timely::dataflow::operators::{Input, Probe};
+
+use timely::Configuration;
+
+use dynamic_scaling_mechanism::{Bin, BinId, BIN_SHIFT, ControlInst};
+use dynamic_scaling_mechanism::control::ControlHandle;
+use dynamic_scaling_mechanism::operator::StatefulOperator;
+use dynamic_scaling_mechanism::probe::MigrationProbe;
+
+#[test]
+fn migration_probe_completion() {
+    timely::execute(Configuration::Process(2), |worker| {
+
+        let mut input = InputHandle::new();
+        let mut control = ControlHandle::new();
+
+        let (mut migration_probe, unattached) = worker.dataflow(|scope| {
+            let control = control.to_stream(scope);
+            let mut migration_probe = MigrationProbe::new(&control);
+            // A probe whose handle is never attached does not report completion
+            let unattached = MigrationProbe::new(&control);
+            scope.input_from(&mut input)
+                .stateful_unary(&control, |key: &u64| *key << (64 - BIN_SHIFT), "Owner", |cap, data, _bin: &mut Bin<_, Vec<()>, _>, output| {
+                    output.session(cap).give_iterator(data.drain(..));
+                })
+                .probe_with(migration_probe.handle());
+            (migration_probe, unattached)
+        });
+
+        // A migration, an abort that comes too late to withdraw it, an invalid batch that is
+        // rejected without a sequence number, and a move
+        assert_eq!(Ok(0), control.send_at(2, vec![ControlInst::Map(vec![1; 1 << BIN_SHIFT])]));
+        assert_eq!(Ok(1), control.send_at(4, vec![ControlInst::Abort(0)]));
+        assert!(control.send_at(5, vec![ControlInst::Move(BinId::new(0), 5)]).is_err());
+        assert_eq!(Ok(2), control.send_at(6, vec![ControlInst::Move(BinId::new(0), 0)]));
+        control.close();
+
+        let sequences = [(0, 2), (1, 4), (2, 6)];
+        for round in 0..10u64 {
+            input.send(round);
+            input.advance_to(round + 1);
+            // Control batches may be observed after the outputs advance
+            let observed = |probe: &MigrationProbe<u64>, sequence| probe.time(sequence).is_some() || probe.is_complete(sequence);
+            while migration_probe.handle().less_than(input.time())
+                || sequences.iter().any(|&(sequence, time)| time <= round && !observed(&migration_probe, sequence)) {
+                worker.step();
+            }
+
+            // All times up to `round` are complete, only pending configurations have a time
+            for &(sequence, time) in &sequences {
+                if time > round && migration_probe.time(sequence).is_none() {
+                    continue;
+                }
+                assert_eq!(time <= round, migration_probe.is_complete(sequence), "sequence {} in round {}", sequence, round);
+                assert_eq!(if time <= round { None } else { Some(time) }, migration_probe.time(sequence));
+                assert!(!unattached.is_complete(sequence));
+            }
+            let completed = sequences.iter().filter(|&&(_, time)| time <= round).map(|&(sequence, _)| sequence).last();
+            assert_eq!(completed, migration_probe.completed(), "round {}", round);
+            assert!(!migration_probe.is_complete(3));
+            assert_eq!(None, unattached.completed());
+        }
+    }).unwrap();
+}
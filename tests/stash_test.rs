@@ -0,0 +1,117 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use timely::dataflow::*;
+use timely::dataflow::operators::{Input, Probe, Inspect};
+
+use timely::Configuration;
+
+use dynamic_scaling_mechanism::{Bin, Control, StatefulConfig};
+use dynamic_scaling_mechanism::operator::StatefulBuilder;
+
+#[test]
+fn spill_and_read_back() {
+    let directory = ::std::env::temp_dir().join(format!("megaphone-stash-test-{}", ::std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    let directory2 = directory.clone();
+    timely::execute(Configuration::Thread, move |worker| {
+
+        let results = results2.clone();
+        let config = StatefulConfig::new().with_stash_limit(64).with_spill_directory(directory2.clone());
+        let mut input = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        let builder_config = config.clone();
+        worker.dataflow(|scope| {
+            let control: Stream<_, Control> = scope.input_from(&mut control_input);
+            let input = scope.input_from(&mut input);
+            StatefulBuilder::new("Spill", &control)
+                .config(builder_config)
+                .unary(&input, |&(key, _): &(u64, Vec<u64>)| key, |cap, data, _bin: &mut Bin<_, Vec<()>, _>, output| {
+                    let mut session = output.session(cap);
+                    for (time, (key, values)) in data.drain(..) {
+                        session.give((time, key, values));
+                    }
+                })
+                .inspect(move |x| results.lock().unwrap().push(x.clone()))
+                .probe_with(&mut probe);
+        });
+
+        // Hold the control input back such that all data is stashed, in batches of varying sizes
+        for round in 0..8u64 {
+            input.send((round, (0..round * 3 + 1).collect::<Vec<_>>()));
+            input.send((round + 100, vec![round; round as usize]));
+            input.advance_to(round + 1);
+            for _ in 0..10 {
+                worker.step();
+            }
+        }
+        assert!(config.spilled_bytes() > 0);
+
+        control_input.advance_to(10);
+        input.advance_to(10);
+        while probe.less_than(input.time()) {
+            worker.step();
+        }
+        assert_eq!(0, config.spilled_bytes());
+        assert_eq!(0, config.stashed_bytes());
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    let mut expected = Vec::new();
+    for round in 0..8u64 {
+        expected.push((round, round, (0..round * 3 + 1).collect::<Vec<_>>()));
+        expected.push((round, round + 100, vec![round; round as usize]));
+    }
+    expected.sort();
+    assert_eq!(expected, results);
+    assert_eq!(0, fs::read_dir(&directory).unwrap().count());
+    fs::remove_dir(&directory).unwrap();
+}
+
+#[test]
+fn stash_wide_alignment() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Thread, move |worker| {
+
+        let results = results2.clone();
+        let mut input = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control: Stream<_, Control> = scope.input_from(&mut control_input);
+            let input = scope.input_from(&mut input);
+            StatefulBuilder::new("Wide", &control)
+                .unary(&input, |&(key, _): &(u64, u128)| key, |cap, data, _bin: &mut Bin<_, Vec<()>, _>, output| {
+                    let mut session = output.session(cap);
+                    for (_time, record) in data.drain(..) {
+                        session.give(record);
+                    }
+                })
+                .inspect(move |x| results.lock().unwrap().push(*x))
+                .probe_with(&mut probe);
+        });
+
+        control_input.advance_to(10);
+        for round in 0..4u64 {
+            input.send((round, (round as u128) << 100));
+            input.advance_to(round + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    assert_eq!((0..4u64).map(|round| (round, (round as u128) << 100)).collect::<Vec<_>>(), results);
+}
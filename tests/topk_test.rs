@@ -0,0 +1,76 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use timely::dataflow::*;
+use timely::dataflow::operators::{Input, Probe, Inspect};
+
+use timely::Configuration;
+
+use dynamic_scaling_mechanism::{BIN_SHIFT, ControlInst, Control};
+use dynamic_scaling_mechanism::topk::TopK;
+
+/// Score updates of `worker` in `round`. Workers update disjoint keys.
+fn updates(worker: u64, round: u64) -> Vec<(u64, Option<u64>)> {
+    let mut updates: Vec<_> = (0..3).map(|i| (worker * 10 + (round + i) % 10, Some((round * 7 + i * 3 + worker * 2) % 13))).collect();
+    if worker == 0 && round == 4 {
+        updates.push((3, None));
+    }
+    updates
+}
+
+#[test]
+fn top_k_across_migration() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index() as u64;
+        let results = results2.clone();
+        let mut input = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control = scope.input_from(&mut control_input);
+            scope.input_from(&mut input)
+                .stateful_top_k(3, &control)
+                .inspect_batch(move |time, data| results.lock().unwrap().extend(data.iter().map(|x| (*time, x.clone()))))
+                .probe_with(&mut probe);
+        });
+
+        // All bins move to worker 1 at time 5
+        control_input.advance_to(5);
+        control_input.send(Control::new(0, 1, ControlInst::Map(vec![1; 1 << BIN_SHIFT])));
+        control_input.advance_to(10);
+        for round in 0..10u64 {
+            for update in updates(index, round) {
+                input.send(update);
+            }
+            input.advance_to(round + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    let mut scores = HashMap::new();
+    let mut expected = Vec::new();
+    for round in 0..10u64 {
+        for (key, score) in updates(0, round).into_iter().chain(updates(1, round)) {
+            match score {
+                Some(score) => { scores.insert(key, score); },
+                None => { scores.remove(&key); },
+            }
+        }
+        let mut top: Vec<(u64, u64)> = scores.iter().map(|(&key, &score)| (key, score)).collect();
+        top.sort_by_key(|&(key, score)| (::std::cmp::Reverse(score), key));
+        top.truncate(3);
+        expected.push((round, top));
+    }
+    assert_eq!(expected, results);
+}
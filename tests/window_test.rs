@@ -0,0 +1,56 @@
+extern crate timely;
+extern crate dynamic_scaling_mechanism;
+
+use std::sync::{Arc, Mutex};
+
+use timely::dataflow::*;
+use timely::dataflow::operators::{Input, Probe, Inspect};
+
+use timely::Configuration;
+
+use dynamic_scaling_mechanism::{BIN_SHIFT, ControlInst, Control};
+use dynamic_scaling_mechanism::window::{Count, Window};
+
+#[test]
+fn tumbling_count_migration() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let results2 = results.clone();
+    timely::execute(Configuration::Process(2), move |worker| {
+
+        let index = worker.index();
+        let results = results2.clone();
+        let mut input = InputHandle::new();
+        let mut control_input = InputHandle::new();
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow(|scope| {
+            let control = scope.input_from(&mut control_input);
+            let input = scope.input_from(&mut input);
+            input
+                .tumbling_window(4, |v: &u64| *v, |t| t, Count, &control)
+                .inspect(move |x| results.lock().unwrap().push(*x))
+                .probe_with(&mut probe);
+        });
+
+        control_input.send(Control::new(0,  1, ControlInst::Map(vec![0; 1 << BIN_SHIFT])));
+        control_input.advance_to(5);
+        control_input.send(Control::new(1,  1, ControlInst::Map(vec![1; 1 << BIN_SHIFT])));
+        control_input.advance_to(20);
+        for round in 0..12u64 {
+            if index == 0 {
+                input.send((round % 2, round));
+            }
+            input.advance_to(round + 1);
+            while probe.less_than(input.time()) {
+                worker.step();
+            }
+        }
+
+    }).unwrap();
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    // the last windows fire once the inputs close
+    assert_eq!(vec![(0, (0, 4), 2), (0, (4, 8), 2), (0, (8, 12), 2),
+                    (1, (0, 4), 2), (1, (4, 8), 2), (1, (8, 12), 2)], results);
+}